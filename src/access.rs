@@ -0,0 +1,77 @@
+use std::net::{AddrParseError, IpAddr};
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    Address(AddrParseError),
+    PrefixLength(String),
+}
+
+// A range of client addresses, written as a single address or in CIDR notation
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        (prefix_len <= max_prefix_len(addr)).then_some(IpNetwork { addr, prefix_len })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // Clients of dual-stack listeners show up with IPv4-mapped addresses
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            addr => addr,
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => prefix_matches(
+                network.to_bits().into(),
+                addr.to_bits().into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                prefix_matches(network.to_bits(), addr.to_bits(), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match network.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (network, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(Error::Address)?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .map_err(|_| Error::PrefixLength(prefix_len.to_owned()))?,
+            None => max_prefix_len(addr),
+        };
+        IpNetwork::new(addr, prefix_len).ok_or_else(|| Error::PrefixLength(prefix_len.to_string()))
+    }
+}
+
+// Whether the client may connect, an empty list allows everyone
+pub(crate) fn is_allowed(allowed_clients: &[IpNetwork], addr: IpAddr) -> bool {
+    allowed_clients.is_empty() || allowed_clients.iter().any(|network| network.contains(addr))
+}
+
+const fn max_prefix_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn prefix_matches(network: u128, addr: u128, bits: u8, prefix_len: u8) -> bool {
+    let host_bits = u32::from(bits - prefix_len);
+    network.checked_shr(host_bits).unwrap_or(0) == addr.checked_shr(host_bits).unwrap_or(0)
+}
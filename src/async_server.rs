@@ -0,0 +1,288 @@
+use crate::access;
+use crate::handler::ServerHandler;
+use crate::selector::DeviceSelector;
+use crate::server::{self, Buffers, Error, ServerBuilder};
+use crate::session::Session;
+use socket2::SockRef;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::Semaphore;
+use tokio::task;
+use tokio::time::{self, Duration};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+// The serial port API offers no way to wait for modem line changes, so they are polled
+const PORT_STATE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Asynchronous server bridging one serial port, several of them can be run concurrently.
+// The serial port is opened for each client and closed when it disconnects,
+// while clients connecting during an active session are turned away.
+// Looking the port up and opening it runs on the blocking threads of tokio. The settings
+// the client asks for and the modem lines are handled in short calls to the port on the
+// runtime's threads, so a multi-threaded runtime keeps a slow device from holding up
+// the other tasks.
+pub struct AsyncServer<H = ()> {
+    serial_port_name: String,
+    listener: TcpListener,
+    session_permit: Arc<Semaphore>,
+    options: Arc<ServerBuilder<H>>,
+}
+
+impl AsyncServer {
+    pub async fn new<A: ToSocketAddrs>(serial_port_name: &str, tcp_addr: A) -> Result<Self, Error> {
+        Self::with_builder(ServerBuilder::new(), serial_port_name, tcp_addr).await
+    }
+}
+
+impl<H: ServerHandler + Clone + Send + Sync + 'static> AsyncServer<H> {
+    // The port is opened with the builder's settings for every client,
+    // so the settings are reset between clients regardless of the policy.
+    // Every client gets its own clone of the builder's handler.
+    pub async fn with_builder<A: ToSocketAddrs>(
+        options: ServerBuilder<H>,
+        serial_port_name: &str,
+        tcp_addr: A,
+    ) -> Result<Self, Error> {
+        // The port itself is only looked up once a client connects
+        serial_port_name
+            .parse::<DeviceSelector>()
+            .map_err(Error::Selector)?;
+        // Resolved by tokio, the std lookup would block the runtime
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host(tcp_addr)
+            .await
+            .map_err(Error::Tcp)?
+            .collect();
+        let listener = server::bind(&addrs[..], options.backlog).map_err(Error::Tcp)?;
+        listener.set_nonblocking(true).map_err(Error::Tcp)?;
+        let listener = TcpListener::from_std(listener).map_err(Error::Tcp)?;
+
+        Ok(AsyncServer {
+            serial_port_name: serial_port_name.to_owned(),
+            listener,
+            session_permit: Arc::new(Semaphore::new(1)),
+            options: Arc::new(options),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listener.local_addr().map_err(Error::Tcp)
+    }
+
+    pub async fn run(&self) -> Result<(), Error> {
+        loop {
+            let (connection, peer_addr) = self.listener.accept().await.map_err(Error::Tcp)?;
+            if !access::is_allowed(&self.options.allowed_clients, peer_addr.ip()) {
+                warn!("refused client {peer_addr}, it is not among the allowed clients");
+                continue;
+            }
+            let Ok(permit) = self.session_permit.clone().try_acquire_owned() else {
+                warn!("refused client {peer_addr}, another client is connected");
+                continue;
+            };
+            let serial_port_name = self.serial_port_name.clone();
+            let options = self.options.clone();
+            tokio::spawn(async move {
+                // A failed session only ends its own connection, the server keeps listening
+                info!("client {peer_addr} connected");
+                match run_session(&serial_port_name, &options, connection).await {
+                    Ok(()) => info!("client {peer_addr} disconnected"),
+                    Err(error) => error!("session of client {peer_addr} failed: {error:?}"),
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+async fn run_session<H: ServerHandler + Clone>(
+    serial_port_name: &str,
+    options: &ServerBuilder<H>,
+    connection: TcpStream,
+) -> Result<(), Error> {
+    let mut port = open(serial_port_name, options).await?;
+    options
+        .socket_options
+        .apply(SockRef::from(&connection))
+        .map_err(Error::Tcp)?;
+    let (mut tcp_reader, mut tcp_writer) = connection.into_split();
+
+    let mut session = Session::with_handler(
+        options.session_config(Some(serial_port_name)),
+        options.handler.clone(),
+    );
+    session.connect();
+    let mut buffers = Buffers::new(options.buffer_size);
+
+    // The port is closed when the session ends, so only the handler needs to know
+    let result: Result<(), Error> = async {
+        loop {
+            match bridge(
+                &mut session,
+                &mut port,
+                &mut tcp_reader,
+                &mut tcp_writer,
+                &mut buffers,
+            )
+            .await
+            {
+                // The client stays connected while the port is reopened
+                Err(Error::Serial(error)) if options.reopen && server::is_port_loss(&error) => {
+                    warn!("lost the serial port, reopening it: {error:?}");
+                    let size = session.port_lost::<io::Error>(&mut buffers.tcp_out)?;
+                    tcp_writer
+                        .write_all(&buffers.tcp_out[..size])
+                        .await
+                        .map_err(Error::Tcp)?;
+                    // Closed first, like `Server` does with a lost port
+                    drop(port);
+                    let reopened = reopen(serial_port_name, options, &mut session);
+                    tokio::pin!(reopened);
+                    // What the client sends meanwhile has no port to go to and is dropped
+                    port = loop {
+                        tokio::select! {
+                            port = &mut reopened => break port,
+                            result = tcp_reader.read(&mut buffers.tcp_data) => match result {
+                                Ok(0) => return Ok(()),
+                                Ok(size) => debug!(
+                                    "dropped {size} bytes from the client while the serial port is lost"
+                                ),
+                                Err(error) if server::is_disconnect(&error) => return Ok(()),
+                                Err(error) => return Err(Error::Tcp(error)),
+                            },
+                        }
+                    };
+                }
+                result => return result,
+            }
+        }
+    }
+    .await;
+    session.handler_mut().on_disconnect();
+    result
+}
+
+// Opens the port in the initial settings, it is looked up again on every open
+// when it is given by a `DeviceSelector`
+async fn open<H>(
+    serial_port_name: &str,
+    options: &ServerBuilder<H>,
+) -> Result<SerialStream, Error> {
+    let selector: DeviceSelector = serial_port_name.parse().map_err(Error::Selector)?;
+    let port_settings = options.config.port_settings;
+    let read_timeout = options.read_timeout;
+    task::spawn_blocking(move || {
+        let name = selector.resolve().map_err(Error::Selector)?;
+        let mut port = tokio_serial::new(name, port_settings.baud_rate)
+            .timeout(read_timeout)
+            .open_native_async()
+            .map_err(Error::SerialInit)?;
+        match port_settings.apply(&mut port).map_err(Error::Serial)? {
+            true => Ok(port),
+            false => Err(Error::UnsupportedSettings(port_settings)),
+        }
+    })
+    .await
+    .map_err(|error| Error::Thread(error.into()))?
+}
+
+// Tries to open the port again until it works, waiting longer after every failed attempt
+async fn reopen<H: ServerHandler>(
+    serial_port_name: &str,
+    options: &ServerBuilder<H>,
+    session: &mut Session<H>,
+) -> SerialStream {
+    let mut delay = options.reopen_delay;
+    loop {
+        time::sleep(delay).await;
+        let restored = open(serial_port_name, options).await.and_then(|mut port| {
+            session.restore(&mut port).map_err(Error::Serial)?;
+            Ok(port)
+        });
+        match restored {
+            Ok(port) => {
+                info!("reopened the serial port");
+                return port;
+            }
+            Err(error) => {
+                delay = delay.saturating_mul(2).min(options.reopen_max_delay);
+                debug!("cannot reopen the serial port yet, retrying in {delay:?}: {error:?}");
+            }
+        }
+    }
+}
+
+// Bridges the data between the client and the serial port until either fails
+// or the client disconnects
+async fn bridge<H: ServerHandler>(
+    session: &mut Session<H>,
+    port: &mut SerialStream,
+    tcp_reader: &mut OwnedReadHalf,
+    tcp_writer: &mut OwnedWriteHalf,
+    buffers: &mut Buffers,
+) -> Result<(), Error> {
+    let Buffers {
+        tcp_data,
+        port_data,
+        port_out,
+        tcp_out,
+    } = buffers;
+    let mut port_state_poll = time::interval(PORT_STATE_POLL_INTERVAL);
+    // When the timed break in progress ends
+    let mut break_deadline = None;
+
+    loop {
+        tokio::select! {
+            result = tcp_reader.read(tcp_data) => {
+                let bytes_read = result.map_err(Error::Tcp)?;
+                if bytes_read == 0 {
+                    return Ok(());
+                }
+                let mut input = &tcp_data[..bytes_read];
+                while !input.is_empty() {
+                    let progress = session.process_network_data(port, input, port_out, tcp_out)?;
+                    port.write_all(&port_out[..progress.serial_produced])
+                        .await
+                        .map_err(Error::Serial)?;
+                    tcp_writer
+                        .write_all(&tcp_out[..progress.network_produced])
+                        .await
+                        .map_err(Error::Tcp)?;
+                    input = &input[progress.consumed..];
+                }
+                if let Some(duration) = session.take_break_timer() {
+                    break_deadline = Some(time::Instant::now() + duration);
+                }
+            }
+            result = port.read(port_data), if !session.is_output_suspended() => {
+                let bytes_read = result.map_err(Error::Serial)?;
+                // A port that was unplugged reads as closed
+                if bytes_read == 0 {
+                    return Err(Error::Serial(io::ErrorKind::UnexpectedEof.into()));
+                }
+                let mut input = &port_data[..bytes_read];
+                while !input.is_empty() {
+                    let progress = session.process_serial_data(input, tcp_out);
+                    tcp_writer
+                        .write_all(&tcp_out[..progress.network_produced])
+                        .await
+                        .map_err(Error::Tcp)?;
+                    input = &input[progress.consumed..];
+                }
+            }
+            _ = time::sleep_until(break_deadline.unwrap_or_else(time::Instant::now)),
+                if break_deadline.is_some() =>
+            {
+                break_deadline = None;
+                session.end_timed_break(port).map_err(Error::Serial)?;
+            }
+            _ = port_state_poll.tick() => {
+                let size = session.process_port_state(port, tcp_out)?;
+                tcp_writer.write_all(&tcp_out[..size]).await.map_err(Error::Tcp)?;
+            }
+        }
+    }
+}
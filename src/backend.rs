@@ -0,0 +1,245 @@
+use crate::com_port::{Control, Parity, StopBits};
+#[cfg(feature = "std")]
+use crate::serialport_conversions::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FlowControl {
+    None,
+    XonXoff,
+    Hardware,
+}
+
+// The serial port operations needed to serve a port over RFC2217.
+// Line setting setters return `Ok(false)` if the backend does not support
+// the requested value, in which case the current value is reported to the client.
+pub trait SerialBackend {
+    type Error;
+
+    // Reads the received data, returning 0 if none is available
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error>;
+    fn flush(&mut self) -> Result<(), Self::Error>;
+
+    fn baud_rate(&mut self) -> Result<u32, Self::Error>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<bool, Self::Error>;
+    fn data_bits(&mut self) -> Result<u8, Self::Error>;
+    fn set_data_bits(&mut self, data_bits: u8) -> Result<bool, Self::Error>;
+    fn parity(&mut self) -> Result<Parity, Self::Error>;
+    fn set_parity(&mut self, parity: Parity) -> Result<bool, Self::Error>;
+    fn stop_bits(&mut self) -> Result<StopBits, Self::Error>;
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<bool, Self::Error>;
+    fn flow_control(&mut self) -> Result<FlowControl, Self::Error>;
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<bool, Self::Error>;
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<(), Self::Error>;
+    fn write_request_to_send(&mut self, level: bool) -> Result<(), Self::Error>;
+    fn read_clear_to_send(&mut self) -> Result<bool, Self::Error>;
+    fn read_data_set_ready(&mut self) -> Result<bool, Self::Error>;
+    fn read_ring_indicator(&mut self) -> Result<bool, Self::Error>;
+    fn read_carrier_detect(&mut self) -> Result<bool, Self::Error>;
+    fn set_break(&mut self, level: bool) -> Result<(), Self::Error>;
+
+    fn clear_input(&mut self) -> Result<(), Self::Error>;
+    fn clear_output(&mut self) -> Result<(), Self::Error>;
+    fn bytes_to_read(&mut self) -> Result<u32, Self::Error>;
+    fn bytes_to_write(&mut self) -> Result<u32, Self::Error>;
+}
+
+impl FlowControl {
+    pub const fn from_control(control: Control) -> Option<Self> {
+        match control {
+            Control::NoFlowControl => Some(Self::None),
+            Control::XonXoffFlowControl => Some(Self::XonXoff),
+            Control::HardwareFlowControl => Some(Self::Hardware),
+            _ => None,
+        }
+    }
+
+    pub const fn to_control(self) -> Control {
+        match self {
+            Self::None => Control::NoFlowControl,
+            Self::XonXoff => Control::XonXoffFlowControl,
+            Self::Hardware => Control::HardwareFlowControl,
+        }
+    }
+
+    // The SET-CONTROL value for the inbound direction, which serial ports share with the outbound one
+    pub const fn to_inbound_control(self) -> Control {
+        match self {
+            Self::None => Control::InboundNoFlowControl,
+            Self::XonXoff => Control::InboundXonXoffFlowControl,
+            Self::Hardware => Control::InboundHardwareFlowControl,
+        }
+    }
+}
+
+// Whether the port took the setting, a value it rejects is refused rather than
+// treated as a failure of the port
+#[cfg(feature = "std")]
+fn applied(result: serialport::Result<()>) -> Result<bool, std::io::Error> {
+    use std::io::ErrorKind;
+    match result {
+        Ok(()) => Ok(true),
+        Err(error)
+            if matches!(
+                error.kind(),
+                serialport::ErrorKind::InvalidInput
+                    | serialport::ErrorKind::Io(ErrorKind::InvalidInput | ErrorKind::Unsupported)
+            ) =>
+        {
+            Ok(false)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+// Pseudo terminals have no modem lines, they are reported inactive and left alone.
+// serialport only tells the ENOTTY the ioctls fail with by its description.
+#[cfg(feature = "std")]
+fn modem_line<T: Default>(result: serialport::Result<T>) -> Result<T, std::io::Error> {
+    match result {
+        Err(error)
+            if error.kind() == serialport::ErrorKind::Unknown
+                && error.description == "Not a typewriter" =>
+        {
+            Ok(T::default())
+        }
+        result => Ok(result?),
+    }
+}
+
+// Adapter serving any `serialport` port, which refuses mark and space parity and 1.5 stop bits
+#[cfg(feature = "std")]
+impl<T: serialport::SerialPort + ?Sized> SerialBackend for T {
+    type Error = std::io::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match std::io::Read::read(self, buf) {
+            Err(error) if error.kind() == std::io::ErrorKind::TimedOut => Ok(0),
+            result => result,
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        std::io::Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        std::io::Write::flush(self)
+    }
+
+    fn baud_rate(&mut self) -> Result<u32, Self::Error> {
+        Ok(serialport::SerialPort::baud_rate(self)?)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<bool, Self::Error> {
+        applied(serialport::SerialPort::set_baud_rate(self, baud_rate))
+    }
+
+    fn data_bits(&mut self) -> Result<u8, Self::Error> {
+        Ok(data_bits_to_u8(serialport::SerialPort::data_bits(self)?))
+    }
+
+    fn set_data_bits(&mut self, data_bits: u8) -> Result<bool, Self::Error> {
+        match u8_to_data_bits(data_bits) {
+            Some(data_bits) => applied(serialport::SerialPort::set_data_bits(self, data_bits)),
+            None => Ok(false),
+        }
+    }
+
+    fn parity(&mut self) -> Result<Parity, Self::Error> {
+        Ok(parity_to_option(serialport::SerialPort::parity(self)?))
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> Result<bool, Self::Error> {
+        match option_to_parity(parity) {
+            Some(parity) => applied(serialport::SerialPort::set_parity(self, parity)),
+            None => Ok(false),
+        }
+    }
+
+    fn stop_bits(&mut self) -> Result<StopBits, Self::Error> {
+        Ok(stop_bits_to_option(serialport::SerialPort::stop_bits(
+            self,
+        )?))
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<bool, Self::Error> {
+        match option_to_stop_bits(stop_bits) {
+            Some(stop_bits) => applied(serialport::SerialPort::set_stop_bits(self, stop_bits)),
+            None => Ok(false),
+        }
+    }
+
+    fn flow_control(&mut self) -> Result<FlowControl, Self::Error> {
+        match serialport::SerialPort::flow_control(self)? {
+            serialport::FlowControl::None => Ok(FlowControl::None),
+            serialport::FlowControl::Software => Ok(FlowControl::XonXoff),
+            serialport::FlowControl::Hardware => Ok(FlowControl::Hardware),
+        }
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<bool, Self::Error> {
+        let flow_control = match flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::XonXoff => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware,
+        };
+        applied(serialport::SerialPort::set_flow_control(self, flow_control))
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<(), Self::Error> {
+        modem_line(serialport::SerialPort::write_data_terminal_ready(
+            self, level,
+        ))
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> Result<(), Self::Error> {
+        modem_line(serialport::SerialPort::write_request_to_send(self, level))
+    }
+
+    fn read_clear_to_send(&mut self) -> Result<bool, Self::Error> {
+        modem_line(serialport::SerialPort::read_clear_to_send(self))
+    }
+
+    fn read_data_set_ready(&mut self) -> Result<bool, Self::Error> {
+        modem_line(serialport::SerialPort::read_data_set_ready(self))
+    }
+
+    fn read_ring_indicator(&mut self) -> Result<bool, Self::Error> {
+        modem_line(serialport::SerialPort::read_ring_indicator(self))
+    }
+
+    fn read_carrier_detect(&mut self) -> Result<bool, Self::Error> {
+        modem_line(serialport::SerialPort::read_carrier_detect(self))
+    }
+
+    fn set_break(&mut self, level: bool) -> Result<(), Self::Error> {
+        match level {
+            true => Ok(serialport::SerialPort::set_break(self)?),
+            false => Ok(serialport::SerialPort::clear_break(self)?),
+        }
+    }
+
+    fn clear_input(&mut self) -> Result<(), Self::Error> {
+        Ok(serialport::SerialPort::clear(
+            self,
+            serialport::ClearBuffer::Input,
+        )?)
+    }
+
+    fn clear_output(&mut self) -> Result<(), Self::Error> {
+        Ok(serialport::SerialPort::clear(
+            self,
+            serialport::ClearBuffer::Output,
+        )?)
+    }
+
+    fn bytes_to_read(&mut self) -> Result<u32, Self::Error> {
+        Ok(serialport::SerialPort::bytes_to_read(self)?)
+    }
+
+    fn bytes_to_write(&mut self) -> Result<u32, Self::Error> {
+        Ok(serialport::SerialPort::bytes_to_write(self)?)
+    }
+}
@@ -0,0 +1,592 @@
+use clap::{ArgAction, ValueEnum};
+use rfc2217_rs::access::IpNetwork;
+use rfc2217_rs::backend;
+use rfc2217_rs::com_port;
+use rfc2217_rs::policy::{Allowed, BaudRatePolicy, LinePolicy};
+use rfc2217_rs::selector::DeviceSelector;
+use rfc2217_rs::{Server, ServerBuilder};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_DEVICE: &str = "/dev/ttyUSB0";
+const DEFAULT_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_TCP_PORT: u16 = 7878;
+const DEFAULT_REOPEN_DELAY_MS: u64 = 100;
+const DEFAULT_REOPEN_MAX_DELAY_MS: u64 = 5000;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+// Written as "1", "1.5" and "2", config files may also use numbers
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "StopBitsValue")]
+pub(crate) enum StopBits {
+    #[value(name = "1")]
+    One,
+    #[value(name = "1.5")]
+    OnePointFive,
+    #[value(name = "2")]
+    Two,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StopBitsValue {
+    Number(f64),
+    Name(String),
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+// Settings the client can be prevented from changing
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[value(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Setting {
+    BaudRate,
+    DataBits,
+    Parity,
+    StopBits,
+    FlowControl,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl TryFrom<StopBitsValue> for StopBits {
+    type Error = String;
+
+    fn try_from(value: StopBitsValue) -> Result<Self, Self::Error> {
+        let name = match value {
+            StopBitsValue::Number(number) => number.to_string(),
+            StopBitsValue::Name(name) => name,
+        };
+        StopBits::from_str(&name, false).map_err(|_| format!("invalid stop bits {name:?}"))
+    }
+}
+
+impl From<Parity> for com_port::Parity {
+    fn from(parity: Parity) -> Self {
+        match parity {
+            Parity::None => com_port::Parity::None,
+            Parity::Odd => com_port::Parity::Odd,
+            Parity::Even => com_port::Parity::Even,
+            Parity::Mark => com_port::Parity::Mark,
+            Parity::Space => com_port::Parity::Space,
+        }
+    }
+}
+
+impl From<StopBits> for com_port::StopBits {
+    fn from(stop_bits: StopBits) -> Self {
+        match stop_bits {
+            StopBits::One => com_port::StopBits::One,
+            StopBits::OnePointFive => com_port::StopBits::OnePointFive,
+            StopBits::Two => com_port::StopBits::Two,
+        }
+    }
+}
+
+impl From<FlowControl> for backend::FlowControl {
+    fn from(flow_control: FlowControl) -> Self {
+        match flow_control {
+            FlowControl::None => backend::FlowControl::None,
+            FlowControl::Software => backend::FlowControl::XonXoff,
+            FlowControl::Hardware => backend::FlowControl::Hardware,
+        }
+    }
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+// Settings of a served port, given on the command line, as the defaults of the config file
+// or for one of its ports. Unset values are taken from the next of these, in that order.
+#[derive(clap::Args, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Options {
+    #[arg(
+        long = "serial_port",
+        short = 'p',
+        help = "Serial port to serve, a path or a selector like usb:303a:1001, \
+                serial-number:ABC123 or by-id:usb-Espressif* [default: /dev/ttyUSB0]"
+    )]
+    pub(crate) device: Option<String>,
+    #[arg(
+        long = "address",
+        short = 'a',
+        help = "Address to listen on [default: 127.0.0.1]"
+    )]
+    address: Option<IpAddr>,
+    #[arg(long = "tcp_port", help = "[default: 7878]")]
+    pub(crate) tcp_port: Option<u16>,
+    #[arg(long = "baud_rate", short = 'b', help = "[default: 9600]")]
+    baud_rate: Option<u32>,
+    #[arg(long = "data_bits", value_parser = clap::value_parser!(u8).range(5..=8), help = "[default: 8]")]
+    data_bits: Option<u8>,
+    #[arg(long = "parity", value_enum, help = "[default: none]")]
+    parity: Option<Parity>,
+    #[arg(long = "stop_bits", value_enum, help = "[default: 1]")]
+    stop_bits: Option<StopBits>,
+    #[arg(long = "flow_control", value_enum, help = "[default: none]")]
+    flow_control: Option<FlowControl>,
+    #[arg(
+        long = "read_timeout",
+        help = "Serial read timeout in milliseconds [default: 0]"
+    )]
+    read_timeout: Option<u64>,
+    #[arg(
+        long = "write_timeout",
+        help = "Milliseconds a client may go without taking its data before it is disconnected [default: 5000]"
+    )]
+    write_timeout: Option<u64>,
+    #[arg(
+        long = "break_duration",
+        help = "Duration of the break sent for the Telnet BREAK command in milliseconds [default: 250]"
+    )]
+    break_duration: Option<u64>,
+    #[arg(long = "signature")]
+    signature: Option<String>,
+    #[arg(long = "nodelay", action = ArgAction::Set, help = "[default: true]")]
+    nodelay: Option<bool>,
+    #[arg(long = "backlog", help = "[default: 128]")]
+    backlog: Option<i32>,
+    #[arg(
+        long = "buffer_size",
+        help = "Bytes read from either side at once [default: 256]"
+    )]
+    buffer_size: Option<usize>,
+    #[arg(long = "send_buffer_size", help = "SO_SNDBUF of client connections")]
+    send_buffer_size: Option<usize>,
+    #[arg(long = "recv_buffer_size", help = "SO_RCVBUF of client connections")]
+    recv_buffer_size: Option<usize>,
+    #[arg(
+        long = "reset_on_disconnect",
+        action = ArgAction::Set,
+        help = "Restore the initial line settings when a client disconnects [default: true]"
+    )]
+    reset_on_disconnect: Option<bool>,
+    #[arg(
+        long = "reopen",
+        action = ArgAction::Set,
+        help = "Reopen the serial port after it is lost, keeping the client connected [default: true]"
+    )]
+    reopen: Option<bool>,
+    #[arg(
+        long = "reopen_delay",
+        help = "Milliseconds before trying to reopen a lost port [default: 100]"
+    )]
+    reopen_delay: Option<u64>,
+    #[arg(
+        long = "reopen_max_delay",
+        help = "Milliseconds the delay between reopen attempts doubles up to [default: 5000]"
+    )]
+    reopen_max_delay: Option<u64>,
+    #[arg(
+        long = "allow_baud_rates",
+        value_delimiter = ',',
+        conflicts_with_all = ["nearest_baud_rates", "min_baud_rate", "max_baud_rate"],
+        help = "Baud rates the client may set, others are refused"
+    )]
+    allow_baud_rates: Option<Vec<u32>>,
+    #[arg(
+        long = "nearest_baud_rates",
+        value_delimiter = ',',
+        conflicts_with_all = ["min_baud_rate", "max_baud_rate"],
+        help = "Baud rates the client's requests are mapped to the closest of"
+    )]
+    nearest_baud_rates: Option<Vec<u32>>,
+    #[arg(
+        long = "min_baud_rate",
+        help = "Lower baud rate requests are raised to this one"
+    )]
+    min_baud_rate: Option<u32>,
+    #[arg(
+        long = "max_baud_rate",
+        help = "Higher baud rate requests are lowered to this one"
+    )]
+    max_baud_rate: Option<u32>,
+    #[arg(long = "allow_data_bits", value_delimiter = ',', value_parser = clap::value_parser!(u8).range(5..=8))]
+    allow_data_bits: Option<Vec<u8>>,
+    #[arg(long = "allow_parity", value_enum, value_delimiter = ',')]
+    allow_parity: Option<Vec<Parity>>,
+    #[arg(long = "allow_stop_bits", value_enum, value_delimiter = ',')]
+    allow_stop_bits: Option<Vec<StopBits>>,
+    #[arg(long = "allow_flow_control", value_enum, value_delimiter = ',')]
+    allow_flow_control: Option<Vec<FlowControl>>,
+    #[arg(
+        long = "lock",
+        value_enum,
+        value_delimiter = ',',
+        help = "Settings the client cannot change"
+    )]
+    lock: Option<Vec<Setting>>,
+    #[arg(
+        long = "allow_clients",
+        value_delimiter = ',',
+        value_parser = parse_network,
+        help = "Addresses or CIDR networks clients may connect from, others are disconnected"
+    )]
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    allow_clients: Option<Vec<IpNetwork>>,
+}
+
+fn parse_network(network: &str) -> Result<IpNetwork, String> {
+    network
+        .parse()
+        .map_err(|error| format!("invalid network {network:?}: {error:?}"))
+}
+
+fn deserialize_networks<'de, D>(deserializer: D) -> Result<Option<Vec<IpNetwork>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|networks| {
+            networks
+                .iter()
+                .map(|network| parse_network(network))
+                .collect()
+        })
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+impl Options {
+    // Takes the values unset here from the other options. The baud rate policy is taken
+    // as a whole, so that a policy given here replaces a different kind of policy there.
+    fn or(self, other: Options) -> Options {
+        let sets_baud_rate_policy = self.allow_baud_rates.is_some()
+            || self.nearest_baud_rates.is_some()
+            || self.min_baud_rate.is_some()
+            || self.max_baud_rate.is_some();
+        let other = match sets_baud_rate_policy {
+            true => Options {
+                allow_baud_rates: None,
+                nearest_baud_rates: None,
+                min_baud_rate: None,
+                max_baud_rate: None,
+                ..other
+            },
+            false => other,
+        };
+        Options {
+            device: self.device.or(other.device),
+            address: self.address.or(other.address),
+            tcp_port: self.tcp_port.or(other.tcp_port),
+            baud_rate: self.baud_rate.or(other.baud_rate),
+            data_bits: self.data_bits.or(other.data_bits),
+            parity: self.parity.or(other.parity),
+            stop_bits: self.stop_bits.or(other.stop_bits),
+            flow_control: self.flow_control.or(other.flow_control),
+            read_timeout: self.read_timeout.or(other.read_timeout),
+            write_timeout: self.write_timeout.or(other.write_timeout),
+            break_duration: self.break_duration.or(other.break_duration),
+            signature: self.signature.or(other.signature),
+            nodelay: self.nodelay.or(other.nodelay),
+            backlog: self.backlog.or(other.backlog),
+            buffer_size: self.buffer_size.or(other.buffer_size),
+            send_buffer_size: self.send_buffer_size.or(other.send_buffer_size),
+            recv_buffer_size: self.recv_buffer_size.or(other.recv_buffer_size),
+            reset_on_disconnect: self.reset_on_disconnect.or(other.reset_on_disconnect),
+            reopen: self.reopen.or(other.reopen),
+            reopen_delay: self.reopen_delay.or(other.reopen_delay),
+            reopen_max_delay: self.reopen_max_delay.or(other.reopen_max_delay),
+            allow_baud_rates: self.allow_baud_rates.or(other.allow_baud_rates),
+            nearest_baud_rates: self.nearest_baud_rates.or(other.nearest_baud_rates),
+            min_baud_rate: self.min_baud_rate.or(other.min_baud_rate),
+            max_baud_rate: self.max_baud_rate.or(other.max_baud_rate),
+            allow_data_bits: self.allow_data_bits.or(other.allow_data_bits),
+            allow_parity: self.allow_parity.or(other.allow_parity),
+            allow_stop_bits: self.allow_stop_bits.or(other.allow_stop_bits),
+            allow_flow_control: self.allow_flow_control.or(other.allow_flow_control),
+            lock: self.lock.or(other.lock),
+            allow_clients: self.allow_clients.or(other.allow_clients),
+        }
+    }
+
+    // The policy lives as long as the server, so the allowed values are leaked
+    fn policy(&self) -> LinePolicy {
+        fn allowed<A: Copy, T>(locked: bool, values: &Option<Vec<A>>) -> Allowed<T>
+        where
+            T: From<A>,
+        {
+            match (locked, values.as_deref()) {
+                (true, _) => Allowed::Locked,
+                (false, None | Some([])) => Allowed::Any,
+                (false, Some(values)) => Allowed::Only(
+                    values
+                        .iter()
+                        .map(|&value| T::from(value))
+                        .collect::<Vec<_>>()
+                        .leak(),
+                ),
+            }
+        }
+        let locked = |setting| {
+            self.lock
+                .as_ref()
+                .is_some_and(|lock| lock.contains(&setting))
+        };
+
+        let baud_rate = if locked(Setting::BaudRate) {
+            BaudRatePolicy::Locked
+        } else if let Some(rates) = self
+            .allow_baud_rates
+            .clone()
+            .filter(|rates| !rates.is_empty())
+        {
+            BaudRatePolicy::Only(rates.leak())
+        } else if let Some(rates) = self
+            .nearest_baud_rates
+            .clone()
+            .filter(|rates| !rates.is_empty())
+        {
+            BaudRatePolicy::Nearest(rates.leak())
+        } else if self.min_baud_rate.is_some() || self.max_baud_rate.is_some() {
+            BaudRatePolicy::Clamp {
+                min: self.min_baud_rate.unwrap_or(0),
+                max: self.max_baud_rate.unwrap_or(u32::MAX),
+            }
+        } else {
+            BaudRatePolicy::Any
+        };
+
+        LinePolicy {
+            baud_rate,
+            data_bits: allowed(locked(Setting::DataBits), &self.allow_data_bits),
+            parity: allowed(locked(Setting::Parity), &self.allow_parity),
+            stop_bits: allowed(locked(Setting::StopBits), &self.allow_stop_bits),
+            flow_control: allowed(locked(Setting::FlowControl), &self.allow_flow_control),
+        }
+    }
+
+    // Checks the values the command line parser does not see when they come from a file
+    fn validate(&self) -> Result<(), String> {
+        let data_bits = self.data_bits.iter();
+        if let Some(data_bits) = data_bits
+            .chain(self.allow_data_bits.iter().flatten())
+            .find(|data_bits| !(5..=8).contains(*data_bits))
+        {
+            return Err(format!("data bits {data_bits} are not in 5..=8"));
+        }
+        if let (Some(min), Some(max)) = (self.min_baud_rate, self.max_baud_rate) {
+            if min > max {
+                return Err(format!("min_baud_rate {min} is above max_baud_rate {max}"));
+            }
+        }
+        let baud_rate_policies = [
+            self.allow_baud_rates.is_some(),
+            self.nearest_baud_rates.is_some(),
+            self.min_baud_rate.is_some() || self.max_baud_rate.is_some(),
+        ];
+        if baud_rate_policies.into_iter().filter(|&set| set).count() > 1 {
+            return Err(
+                "allow_baud_rates, nearest_baud_rates and min/max_baud_rate exclude each other"
+                    .to_owned(),
+            );
+        }
+        Ok(())
+    }
+
+    fn builder(&self) -> ServerBuilder {
+        fn with<T>(
+            builder: ServerBuilder,
+            value: Option<T>,
+            set: impl FnOnce(ServerBuilder, T) -> ServerBuilder,
+        ) -> ServerBuilder {
+            match value {
+                Some(value) => set(builder, value),
+                None => builder,
+            }
+        }
+
+        let builder = Server::builder().policy(self.policy());
+        let builder = with(builder, self.baud_rate, ServerBuilder::baud_rate);
+        let builder = with(builder, self.data_bits, ServerBuilder::data_bits);
+        let builder = with(builder, self.parity, |builder, parity| {
+            builder.parity(parity.into())
+        });
+        let builder = with(builder, self.stop_bits, |builder, stop_bits| {
+            builder.stop_bits(stop_bits.into())
+        });
+        let builder = with(builder, self.flow_control, |builder, flow_control| {
+            builder.flow_control(flow_control.into())
+        });
+        let builder = with(builder, self.read_timeout, |builder, timeout| {
+            builder.read_timeout(Duration::from_millis(timeout))
+        });
+        let builder = with(builder, self.write_timeout, |builder, timeout| {
+            builder.write_timeout(Duration::from_millis(timeout))
+        });
+        let builder = with(builder, self.break_duration, |builder, duration| {
+            builder.break_duration(Duration::from_millis(duration))
+        });
+        let builder = with(builder, self.signature.as_deref(), ServerBuilder::signature);
+        let builder = with(builder, self.nodelay, ServerBuilder::nodelay);
+        let builder = with(builder, self.backlog, ServerBuilder::backlog);
+        let builder = with(builder, self.buffer_size, ServerBuilder::buffer_size);
+        let builder = with(
+            builder,
+            self.send_buffer_size,
+            ServerBuilder::send_buffer_size,
+        );
+        let builder = with(
+            builder,
+            self.recv_buffer_size,
+            ServerBuilder::recv_buffer_size,
+        );
+        let builder = with(
+            builder,
+            self.reset_on_disconnect,
+            ServerBuilder::reset_on_disconnect,
+        );
+        // Unlike the library, the server reopens lost ports unless told otherwise
+        let builder = builder.reopen(self.reopen.unwrap_or(true));
+        let builder = builder.reopen_delay(
+            Duration::from_millis(self.reopen_delay.unwrap_or(DEFAULT_REOPEN_DELAY_MS)),
+            Duration::from_millis(self.reopen_max_delay.unwrap_or(DEFAULT_REOPEN_MAX_DELAY_MS)),
+        );
+        with(
+            builder,
+            self.allow_clients.clone(),
+            ServerBuilder::allow_clients,
+        )
+    }
+}
+
+// A config file holds the defaults of every port and the ports to serve
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    // Applies to the whole process, so it cannot be set per port
+    pub(crate) log_level: Option<LogLevel>,
+    #[serde(default)]
+    defaults: Options,
+    #[serde(default)]
+    ports: Vec<Options>,
+}
+
+impl ConfigFile {
+    // Reads a TOML or YAML file, told apart by the extension
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| format!("cannot read {}: {error}", path.display()))?;
+        match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => toml::from_str(&content).map_err(|error| error.to_string()),
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(&content).map_err(|error| error.to_string())
+            }
+            _ => Err("expected a .toml, .yaml or .yml config file".to_owned()),
+        }
+        .map_err(|error| format!("{}: {error}", path.display()))
+    }
+}
+
+// A serial port served on a TCP address
+pub(crate) struct Bridge {
+    pub(crate) device: String,
+    pub(crate) tcp_addr: SocketAddr,
+    pub(crate) builder: ServerBuilder,
+}
+
+// Resolves the bridges to run from the command line, the device=port list and the config file.
+// Without any listed ports, a single port is served from the command line and file defaults.
+pub(crate) fn bridges(
+    cli: &Options,
+    listed: Vec<(String, u16)>,
+    config: ConfigFile,
+) -> Result<Vec<Bridge>, String> {
+    let mut ports: Vec<Options> = listed
+        .into_iter()
+        .map(|(device, tcp_port)| Options {
+            device: Some(device),
+            tcp_port: Some(tcp_port),
+            ..Options::default()
+        })
+        .collect();
+    ports.extend(config.ports);
+    // The single port takes its device and TCP port from the command line or the file
+    // defaults, the built-in ones only apply when neither sets them
+    let single = ports.is_empty();
+    if single {
+        ports.push(Options::default());
+    } else if cli.device.is_some() || cli.tcp_port.is_some() {
+        return Err(
+            "serial_port and tcp_port cannot be given along with a list of ports".to_owned(),
+        );
+    }
+
+    let mut devices = HashSet::new();
+    let mut tcp_addrs = HashSet::new();
+    ports
+        .into_iter()
+        .enumerate()
+        .map(|(index, port)| {
+            let mut options = cli.clone().or(port).or(config.defaults.clone());
+            if single {
+                options
+                    .device
+                    .get_or_insert_with(|| DEFAULT_DEVICE.to_owned());
+                options.tcp_port.get_or_insert(DEFAULT_TCP_PORT);
+            }
+            let device = options.device.clone();
+            let (Some(device), Some(tcp_port)) = (device, options.tcp_port) else {
+                return Err(format!("port {} needs a device and a tcp_port", index + 1));
+            };
+            options
+                .validate()
+                .map_err(|error| format!("{device}: {error}"))?;
+            device
+                .parse::<DeviceSelector>()
+                .map_err(|error| format!("{device}: invalid selector, {error:?}"))?;
+            let tcp_addr = SocketAddr::new(options.address.unwrap_or(DEFAULT_ADDRESS), tcp_port);
+            if !devices.insert(device.clone()) {
+                return Err(format!("{device} is served more than once"));
+            }
+            if !tcp_addrs.insert(tcp_addr) {
+                return Err(format!("{tcp_addr} is used by more than one port"));
+            }
+            Ok(Bridge {
+                builder: options.builder(),
+                device,
+                tcp_addr,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,32 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::thread;
+
+// Writes the records to stderr along with the thread they come from,
+// the bridge threads are named after their serial port
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let thread = thread::current();
+            eprintln!(
+                "{:<5} [{}] {}",
+                record.level(),
+                thread.name().unwrap_or("?"),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub(crate) fn init(level: LevelFilter) {
+    // Only fails when called twice
+    let _ = log::set_logger(&Logger);
+    log::set_max_level(level);
+}
@@ -0,0 +1,156 @@
+mod config;
+mod logger;
+
+use clap::{CommandFactory, Parser};
+use config::{Bridge, ConfigFile, LogLevel, Options};
+use rfc2217_rs::MultiServer;
+use serialport::SerialPortType;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[clap(
+        long = "config",
+        short = 'c',
+        help = "TOML or YAML file with the defaults and the ports to serve, flags override it"
+    )]
+    config: Option<PathBuf>,
+    #[clap(
+        long = "check_config",
+        alias = "check-config",
+        help = "Check the configuration, list the ports it serves and exit"
+    )]
+    check_config: bool,
+    #[clap(
+        long = "list_ports",
+        alias = "list-ports",
+        help = "List the available serial ports with the selectors matching them and exit"
+    )]
+    list_ports: bool,
+    #[clap(
+        long = "bridge",
+        value_parser = parse_bridge,
+        value_delimiter = ',',
+        help = "Serial port served on a TCP port as device=port, replaces serial_port and tcp_port"
+    )]
+    bridges: Vec<(String, u16)>,
+    #[clap(
+        long = "bridges_file",
+        help = "File listing device=port bridges, one per line"
+    )]
+    bridges_file: Option<PathBuf>,
+    #[clap(
+        short = 'v',
+        long = "log_level",
+        alias = "log-level",
+        value_enum,
+        help = "Events logged to stderr, debug also logs the protocol exchange [default: info]"
+    )]
+    log_level: Option<LogLevel>,
+    #[clap(flatten)]
+    options: Options,
+}
+
+// Parses a device=port bridge, the device name may itself contain '='
+fn parse_bridge(bridge: &str) -> Result<(String, u16), String> {
+    let (device, port) = bridge
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected device=port, found {bridge:?}"))?;
+    let port = port
+        .parse()
+        .map_err(|error| format!("invalid TCP port {port:?}: {error}"))?;
+    Ok((device.to_owned(), port))
+}
+
+// Reads the bridges, skipping empty lines and lines starting with '#'
+fn read_bridges_file(path: &Path) -> Result<Vec<(String, u16)>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| format!("cannot read {}: {error}", path.display()))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_bridge)
+        .collect()
+}
+
+// Prints each port with its USB metadata, written the way the device selectors take it
+fn list_ports() -> Result<(), String> {
+    let ports = serialport::available_ports()
+        .map_err(|error| format!("cannot list the serial ports: {error}"))?;
+    for port in ports {
+        let details = match port.port_type {
+            SerialPortType::UsbPort(usb) => {
+                let mut details = format!("usb:{:04x}:{:04x}", usb.vid, usb.pid);
+                if let Some(serial_number) = usb.serial_number {
+                    details += &format!(" serial-number:{serial_number}");
+                }
+                for name in [usb.manufacturer, usb.product].into_iter().flatten() {
+                    details += &format!(" {name:?}");
+                }
+                details
+            }
+            SerialPortType::PciPort => "pci".to_owned(),
+            SerialPortType::BluetoothPort => "bluetooth".to_owned(),
+            SerialPortType::Unknown => "unknown".to_owned(),
+        };
+        println!("{} {details}", port.port_name);
+    }
+    Ok(())
+}
+
+impl Args {
+    fn config_file(&self) -> Result<ConfigFile, String> {
+        match &self.config {
+            Some(path) => ConfigFile::load(path),
+            None => Ok(ConfigFile::default()),
+        }
+    }
+
+    fn bridges(&self, config: ConfigFile) -> Result<Vec<Bridge>, String> {
+        let mut listed = self.bridges.clone();
+        if let Some(path) = &self.bridges_file {
+            listed.extend(read_bridges_file(path)?);
+        }
+        config::bridges(&self.options, listed, config)
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let exit_with = |error| -> ! {
+        Args::command()
+            .error(clap::error::ErrorKind::InvalidValue, error)
+            .exit()
+    };
+    if args.list_ports {
+        list_ports().unwrap_or_else(|error| exit_with(error));
+        return;
+    }
+    let config = args.config_file().unwrap_or_else(|error| exit_with(error));
+    let log_level = args
+        .log_level
+        .or(config.log_level)
+        .unwrap_or(LogLevel::Info);
+    logger::init(log_level.into());
+    let bridges = args
+        .bridges(config)
+        .unwrap_or_else(|error| exit_with(error));
+
+    if args.check_config {
+        for bridge in &bridges {
+            println!("{} on {}", bridge.device, bridge.tcp_addr);
+        }
+        return;
+    }
+
+    // Each bridge runs on its own, the process only exits once all of them failed
+    let server = bridges
+        .into_iter()
+        .fold(MultiServer::new(), |server, bridge| {
+            log::info!("serving {} on {}", bridge.device, bridge.tcp_addr);
+            server.add(bridge.builder, &bridge.device, bridge.tcp_addr)
+        });
+    server.run(|device, error| log::error!("{device} stopped: {error:?}"));
+    std::process::exit(1);
+}
@@ -0,0 +1,565 @@
+use crate::com_port::{self, Control, ModemState, Purge};
+use crate::negotiation::{Change, OptionState, OptionTable, Side};
+use crate::serialport_conversions::*;
+use crate::{encoder, negotiation, parser, subnegotiation, Negotiation, Parser, Subnegotiation};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+// How long to wait for the server to acknowledge a negotiation or a subnegotiation
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug)]
+pub enum Error {
+    Parsing(parser::Error),
+    Tcp(io::Error),
+    Serial(serialport::Error),
+    Timeout,
+    ComPortRefused,
+    // The server could not apply the requested setting, it answered with the one in effect
+    ParityRefused(com_port::Parity),
+    StopBitsRefused(com_port::StopBits),
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Tcp(error) => error,
+            Error::Serial(error) => error.into(),
+            Error::Parsing(error) => {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{error:?}"))
+            }
+            Error::Timeout => {
+                io::Error::new(io::ErrorKind::TimedOut, "RFC2217 server did not answer")
+            }
+            Error::ComPortRefused => io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Server refused the COM-PORT-OPTION",
+            ),
+            Error::ParityRefused(parity) => io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("RFC2217 server did not accept the requested parity, it uses {parity:?}"),
+            ),
+            Error::StopBitsRefused(stop_bits) => io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "RFC2217 server did not accept the requested stop bits, it uses {stop_bits:?}"
+                ),
+            ),
+        }
+    }
+}
+
+impl From<Error> for serialport::Error {
+    fn from(error: Error) -> Self {
+        io::Error::from(error).into()
+    }
+}
+
+// A blocking RFC2217 client, usable as a drop-in replacement for a local serial port
+pub struct Client {
+    conn: RefCell<Connection>,
+    timeout: Duration,
+    baud_rate: u32,
+    data_bits: DataBits,
+    // Kept as option values, which include settings `serialport` has no equivalent for
+    parity: com_port::Parity,
+    stop_bits: com_port::StopBits,
+    flow_control: FlowControl,
+}
+
+struct Connection {
+    tcp_conn: TcpStream,
+    parser: Parser,
+    data: VecDeque<u8>,
+    answers: VecDeque<Subnegotiation>,
+    options: OptionTable,
+    modem_state: ModemState,
+    // The server asked to stop sending data until it resumes
+    suspended: bool,
+}
+
+impl Client {
+    pub fn new<A: ToSocketAddrs>(tcp_addr: A, baud_rate: u32) -> Result<Self, Error> {
+        let tcp_conn = TcpStream::connect(tcp_addr).map_err(Error::Tcp)?;
+        tcp_conn.set_nodelay(true).map_err(Error::Tcp)?;
+
+        let mut client = Client {
+            conn: RefCell::new(Connection {
+                tcp_conn,
+                parser: Parser::new(),
+                data: VecDeque::new(),
+                answers: VecDeque::new(),
+                options: OptionTable::new(),
+                modem_state: ModemState::empty(),
+                suspended: false,
+            }),
+            timeout: Duration::from_millis(0),
+            baud_rate,
+            data_bits: DataBits::Eight,
+            parity: com_port::Parity::None,
+            stop_bits: com_port::StopBits::One,
+            flow_control: FlowControl::None,
+        };
+
+        client.conn.get_mut().negotiate()?;
+        client.set_baud_rate(baud_rate).map_err(Error::Serial)?;
+        client
+            .set_data_bits(DataBits::Eight)
+            .map_err(Error::Serial)?;
+        client.set_parity(Parity::None).map_err(Error::Serial)?;
+        client.set_stop_bits(StopBits::One).map_err(Error::Serial)?;
+        client
+            .set_flow_control(FlowControl::None)
+            .map_err(Error::Serial)?;
+
+        Ok(client)
+    }
+
+    fn request<T>(
+        &self,
+        subnegotiation: Subnegotiation,
+        answer: impl FnMut(Subnegotiation) -> Option<T>,
+    ) -> Result<T, Error> {
+        let mut conn = self.conn.borrow_mut();
+        conn.send_subnegotiation(subnegotiation)?;
+        conn.wait_for_answer(answer)
+    }
+
+    fn set_control(&self, val: Control, answers: &[Control]) -> Result<Control, Error> {
+        self.request(Subnegotiation::SetControl(val), |answer| match answer {
+            Subnegotiation::SetControl(answer) if answers.contains(&answer) => Some(answer),
+            _ => None,
+        })
+    }
+
+    pub fn parity_option(&self) -> com_port::Parity {
+        self.parity
+    }
+
+    // Also sets mark and space parity, when the server's port supports them
+    pub fn set_parity_option(&mut self, parity: com_port::Parity) -> Result<(), Error> {
+        let answer = self.request(Subnegotiation::SetParity(parity), |answer| match answer {
+            Subnegotiation::SetParity(val) => Some(val),
+            _ => None,
+        })?;
+        self.parity = answer;
+        match answer == parity {
+            true => Ok(()),
+            false => Err(Error::ParityRefused(answer)),
+        }
+    }
+
+    pub fn stop_bits_option(&self) -> com_port::StopBits {
+        self.stop_bits
+    }
+
+    // Also sets 1.5 stop bits, when the server's port supports them
+    pub fn set_stop_bits_option(&mut self, stop_bits: com_port::StopBits) -> Result<(), Error> {
+        let answer = self.request(
+            Subnegotiation::SetStopSize(stop_bits),
+            |answer| match answer {
+                Subnegotiation::SetStopSize(val) => Some(val),
+                _ => None,
+            },
+        )?;
+        self.stop_bits = answer;
+        match answer == stop_bits {
+            true => Ok(()),
+            false => Err(Error::StopBitsRefused(answer)),
+        }
+    }
+
+    fn read_modem_state(&self, line: ModemState) -> serialport::Result<bool> {
+        let mut conn = self.conn.borrow_mut();
+        conn.receive(Duration::ZERO)?;
+        Ok(conn.modem_state.contains(line))
+    }
+}
+
+impl Connection {
+    fn negotiate(&mut self) -> Result<(), Error> {
+        let requests = [
+            (Side::Local, negotiation::Option::Binary),
+            (Side::Remote, negotiation::Option::Binary),
+            (Side::Local, negotiation::Option::SuppressGoAhead),
+            (Side::Remote, negotiation::Option::SuppressGoAhead),
+            (Side::Local, negotiation::Option::ComPort),
+        ];
+        for (side, option) in requests {
+            self.options.support(side, option);
+            if let Some(request) = self.options.request_enable(side, option) {
+                self.send_negotiation(request)?;
+            }
+        }
+
+        let deadline = Instant::now() + ANSWER_TIMEOUT;
+        loop {
+            match self
+                .options
+                .state(Side::Local, negotiation::Option::ComPort)
+            {
+                OptionState::Yes => return Ok(()),
+                OptionState::No => return Err(Error::ComPortRefused),
+                _ => {}
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::Timeout);
+            }
+            self.receive(remaining)?;
+        }
+    }
+
+    fn wait_for_answer<T>(
+        &mut self,
+        mut answer: impl FnMut(Subnegotiation) -> Option<T>,
+    ) -> Result<T, Error> {
+        let deadline = Instant::now() + ANSWER_TIMEOUT;
+        loop {
+            while let Some(subnegotiation) = self.answers.pop_front() {
+                if let Some(value) = answer(subnegotiation) {
+                    return Ok(value);
+                }
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::Timeout);
+            }
+            self.receive(remaining)?;
+        }
+    }
+
+    // Reads whatever the server sent within the timeout and processes it,
+    // a zero timeout only processes the data that is already available
+    fn receive(&mut self, timeout: Duration) -> Result<(), Error> {
+        let mut tcp_data = [0; 256];
+        let result = if timeout.is_zero() {
+            self.tcp_conn.set_nonblocking(true).map_err(Error::Tcp)?;
+            let result = self.tcp_conn.read(&mut tcp_data);
+            self.tcp_conn.set_nonblocking(false).map_err(Error::Tcp)?;
+            result
+        } else {
+            self.tcp_conn
+                .set_read_timeout(Some(timeout))
+                .map_err(Error::Tcp)?;
+            self.tcp_conn.read(&mut tcp_data)
+        };
+
+        match result {
+            Ok(0) => Err(Error::Tcp(io::ErrorKind::UnexpectedEof.into())),
+            Ok(bytes_read) => {
+                for &byte in &tcp_data[..bytes_read] {
+                    if let Some(event) = self.parser.process_byte(byte).map_err(Error::Parsing)? {
+                        self.process_event(event)?;
+                    }
+                }
+                Ok(())
+            }
+            Err(error) => match error.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Ok(()),
+                _ => Err(Error::Tcp(error)),
+            },
+        }
+    }
+
+    fn process_event(&mut self, event: parser::Event) -> Result<(), Error> {
+        match event {
+            parser::Event::Data(byte) => self.data.push_back(byte),
+            parser::Event::Command(_) => {}
+            parser::Event::Negotiation(negotiation) => self.process_negotiation(negotiation)?,
+            parser::Event::Subnegotiation(Subnegotiation::NotifyModemState(modem_state)) => {
+                self.modem_state = modem_state;
+            }
+            parser::Event::Subnegotiation(Subnegotiation::NotifyLineState(_)) => {}
+            parser::Event::Subnegotiation(Subnegotiation::FlowControlSuspend) => {
+                self.suspended = true;
+            }
+            parser::Event::Subnegotiation(Subnegotiation::FlowControlResume) => {
+                self.suspended = false;
+            }
+            parser::Event::Subnegotiation(subnegotiation) => self.answers.push_back(subnegotiation),
+        }
+        Ok(())
+    }
+
+    fn process_negotiation(&mut self, negotiation: Negotiation) -> Result<(), Error> {
+        let reply = self.options.receive(negotiation);
+        if let Some(answer) = reply.answer {
+            self.send_negotiation(answer)?;
+        }
+        // Nothing can be set once the server stops using the COM-PORT-OPTION
+        match reply.change {
+            Some(Change::Disabled(Side::Local, negotiation::Option::ComPort)) => {
+                Err(Error::ComPortRefused)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn send_negotiation(&mut self, negotiation: Negotiation) -> Result<(), Error> {
+        let mut buf = [0; negotiation::SIZE];
+        negotiation.serialize(&mut buf);
+        self.tcp_conn.write_all(&buf).map_err(Error::Tcp)
+    }
+
+    fn send_subnegotiation(&mut self, subnegotiation: Subnegotiation) -> Result<(), Error> {
+        let mut buf = [0; subnegotiation::MAX_SIZE];
+        let size = subnegotiation.serialize_client(&mut buf);
+        self.tcp_conn.write_all(&buf[..size]).map_err(Error::Tcp)
+    }
+}
+
+impl Read for Client {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = self.timeout;
+        let conn = self.conn.get_mut();
+        let deadline = Instant::now() + timeout;
+        while conn.data.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            conn.receive(remaining)?;
+            if conn.data.is_empty() && remaining.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Operation timed out",
+                ));
+            }
+        }
+
+        let size = buf.len().min(conn.data.len());
+        for (dst, src) in buf.iter_mut().zip(conn.data.drain(..size)) {
+            *dst = src;
+        }
+        Ok(size)
+    }
+}
+
+impl Write for Client {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let timeout = self.timeout;
+        let conn = self.conn.get_mut();
+        let mut escaped = [0; 512];
+        let mut input = buf;
+        while !input.is_empty() {
+            // Wait for the server to resume if it is falling behind writing to the serial port
+            conn.receive(Duration::ZERO)?;
+            let deadline = Instant::now() + timeout;
+            while conn.suspended {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() && input.len() < buf.len() {
+                    return Ok(buf.len() - input.len());
+                }
+                if remaining.is_zero() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Data flow suspended by the server",
+                    ));
+                }
+                conn.receive(remaining)?;
+            }
+
+            let encoded = encoder::encode_data(input, &mut escaped);
+            conn.tcp_conn.write_all(&escaped[..encoded.produced])?;
+            input = &input[encoded.consumed..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.conn.get_mut().tcp_conn.flush()
+    }
+}
+
+impl SerialPort for Client {
+    fn name(&self) -> Option<String> {
+        let addr = self.conn.borrow().tcp_conn.peer_addr().ok()?;
+        Some(format!("rfc2217://{addr}"))
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        option_to_parity(self.parity).ok_or_else(|| {
+            no_equivalent(format!(
+                "{:?} parity, see Client::parity_option",
+                self.parity
+            ))
+        })
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        option_to_stop_bits(self.stop_bits).ok_or_else(|| {
+            no_equivalent(format!(
+                "{:?} stop bits, see Client::stop_bits_option",
+                self.stop_bits
+            ))
+        })
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.baud_rate = self.request(
+            Subnegotiation::SetBaudRate(baud_rate),
+            |answer| match answer {
+                Subnegotiation::SetBaudRate(val) => Some(val),
+                _ => None,
+            },
+        )?;
+        check_accepted(self.baud_rate == baud_rate, "baud rate")
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        let answer = self.request(
+            Subnegotiation::SetDataSize(data_bits_to_u8(data_bits)),
+            |answer| match answer {
+                Subnegotiation::SetDataSize(val) => Some(u8_to_data_bits(val)),
+                _ => None,
+            },
+        )?;
+        if let Some(answer) = answer {
+            self.data_bits = answer;
+        }
+        check_accepted(answer == Some(data_bits), "data bits")
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        let answer = self.set_control(
+            flow_control_to_option(flow_control),
+            &[
+                Control::NoFlowControl,
+                Control::XonXoffFlowControl,
+                Control::HardwareFlowControl,
+            ],
+        )?;
+        let answer = option_to_flow_control(answer);
+        if let Some(answer) = answer {
+            self.flow_control = answer;
+        }
+        check_accepted(answer == Some(flow_control), "flow control")
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        Ok(self.set_parity_option(parity_to_option(parity))?)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        Ok(self.set_stop_bits_option(stop_bits_to_option(stop_bits))?)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        let val = if level {
+            Control::RtsOn
+        } else {
+            Control::RtsOff
+        };
+        let answer = self.set_control(val, &[Control::RtsOn, Control::RtsOff])?;
+        check_accepted(answer == val, "RTS state")
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        let val = if level {
+            Control::DtrOn
+        } else {
+            Control::DtrOff
+        };
+        let answer = self.set_control(val, &[Control::DtrOn, Control::DtrOff])?;
+        check_accepted(answer == val, "DTR state")
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        self.read_modem_state(ModemState::CTS)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        self.read_modem_state(ModemState::DSR)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        self.read_modem_state(ModemState::RI)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        self.read_modem_state(ModemState::CD)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.conn.borrow().data.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        let val = match buffer_to_clear {
+            ClearBuffer::Input => Purge::ReceiveBuffer,
+            ClearBuffer::Output => Purge::TransmitBuffer,
+            ClearBuffer::All => Purge::BothBuffers,
+        };
+        self.request(Subnegotiation::PurgeData(val), |answer| match answer {
+            Subnegotiation::PurgeData(_) => Some(()),
+            _ => None,
+        })?;
+        if val != Purge::TransmitBuffer {
+            self.conn.borrow_mut().data.clear();
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Err(serialport::Error::new(
+            serialport::ErrorKind::Unknown,
+            "Cloning an RFC2217 client is not supported",
+        ))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        let answer = self.set_control(Control::BreakOn, &[Control::BreakOn, Control::BreakOff])?;
+        check_accepted(answer == Control::BreakOn, "break state")
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        let answer = self.set_control(Control::BreakOff, &[Control::BreakOn, Control::BreakOff])?;
+        check_accepted(answer == Control::BreakOff, "break state")
+    }
+}
+
+// The server answers with the value actually in effect, which differs from
+// the requested one if the server could not apply it
+fn check_accepted(accepted: bool, setting: &str) -> serialport::Result<()> {
+    match accepted {
+        true => Ok(()),
+        false => Err(serialport::Error::new(
+            serialport::ErrorKind::InvalidInput,
+            format!("RFC2217 server did not accept the requested {setting}"),
+        )),
+    }
+}
+
+fn no_equivalent(setting: String) -> serialport::Error {
+    serialport::Error::new(
+        serialport::ErrorKind::InvalidInput,
+        format!("The remote port uses {setting}, which serialport cannot represent"),
+    )
+}
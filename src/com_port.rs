@@ -0,0 +1,304 @@
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+
+// The SET-PARITY value, like the other COM-PORT-OPTION values in this file
+// defined here: https://www.rfc-editor.org/rfc/rfc2217.html
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Parity {
+    Request,
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+    Unknown(u8),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StopBits {
+    Request,
+    One,
+    Two,
+    OnePointFive,
+    Unknown(u8),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Control {
+    RequestFlowControl,
+    NoFlowControl,
+    XonXoffFlowControl,
+    HardwareFlowControl,
+    RequestBreak,
+    BreakOn,
+    BreakOff,
+    RequestDtr,
+    DtrOn,
+    DtrOff,
+    RequestRts,
+    RtsOn,
+    RtsOff,
+    RequestInboundFlowControl,
+    InboundNoFlowControl,
+    InboundXonXoffFlowControl,
+    InboundHardwareFlowControl,
+    DcdFlowControl,
+    InboundDtrFlowControl,
+    DsrFlowControl,
+    Unknown(u8),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Purge {
+    ReceiveBuffer,
+    TransmitBuffer,
+    BothBuffers,
+    Unknown(u8),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct LineState(u8);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ModemState(u8);
+
+impl Parity {
+    pub const fn from_u8(byte: u8) -> Self {
+        match byte {
+            0 => Self::Request,
+            1 => Self::None,
+            2 => Self::Odd,
+            3 => Self::Even,
+            4 => Self::Mark,
+            5 => Self::Space,
+            _ => Self::Unknown(byte),
+        }
+    }
+
+    pub const fn to_u8(self) -> u8 {
+        match self {
+            Self::Request => 0,
+            Self::None => 1,
+            Self::Odd => 2,
+            Self::Even => 3,
+            Self::Mark => 4,
+            Self::Space => 5,
+            Self::Unknown(byte) => byte,
+        }
+    }
+}
+
+impl StopBits {
+    pub const fn from_u8(byte: u8) -> Self {
+        match byte {
+            0 => Self::Request,
+            1 => Self::One,
+            2 => Self::Two,
+            3 => Self::OnePointFive,
+            _ => Self::Unknown(byte),
+        }
+    }
+
+    pub const fn to_u8(self) -> u8 {
+        match self {
+            Self::Request => 0,
+            Self::One => 1,
+            Self::Two => 2,
+            Self::OnePointFive => 3,
+            Self::Unknown(byte) => byte,
+        }
+    }
+}
+
+impl Control {
+    pub const fn from_u8(byte: u8) -> Self {
+        match byte {
+            0 => Self::RequestFlowControl,
+            1 => Self::NoFlowControl,
+            2 => Self::XonXoffFlowControl,
+            3 => Self::HardwareFlowControl,
+            4 => Self::RequestBreak,
+            5 => Self::BreakOn,
+            6 => Self::BreakOff,
+            7 => Self::RequestDtr,
+            8 => Self::DtrOn,
+            9 => Self::DtrOff,
+            10 => Self::RequestRts,
+            11 => Self::RtsOn,
+            12 => Self::RtsOff,
+            13 => Self::RequestInboundFlowControl,
+            14 => Self::InboundNoFlowControl,
+            15 => Self::InboundXonXoffFlowControl,
+            16 => Self::InboundHardwareFlowControl,
+            17 => Self::DcdFlowControl,
+            18 => Self::InboundDtrFlowControl,
+            19 => Self::DsrFlowControl,
+            _ => Self::Unknown(byte),
+        }
+    }
+
+    pub const fn to_u8(self) -> u8 {
+        match self {
+            Self::RequestFlowControl => 0,
+            Self::NoFlowControl => 1,
+            Self::XonXoffFlowControl => 2,
+            Self::HardwareFlowControl => 3,
+            Self::RequestBreak => 4,
+            Self::BreakOn => 5,
+            Self::BreakOff => 6,
+            Self::RequestDtr => 7,
+            Self::DtrOn => 8,
+            Self::DtrOff => 9,
+            Self::RequestRts => 10,
+            Self::RtsOn => 11,
+            Self::RtsOff => 12,
+            Self::RequestInboundFlowControl => 13,
+            Self::InboundNoFlowControl => 14,
+            Self::InboundXonXoffFlowControl => 15,
+            Self::InboundHardwareFlowControl => 16,
+            Self::DcdFlowControl => 17,
+            Self::InboundDtrFlowControl => 18,
+            Self::DsrFlowControl => 19,
+            Self::Unknown(byte) => byte,
+        }
+    }
+}
+
+impl Purge {
+    pub const fn from_u8(byte: u8) -> Self {
+        match byte {
+            1 => Self::ReceiveBuffer,
+            2 => Self::TransmitBuffer,
+            3 => Self::BothBuffers,
+            _ => Self::Unknown(byte),
+        }
+    }
+
+    pub const fn to_u8(self) -> u8 {
+        match self {
+            Self::ReceiveBuffer => 1,
+            Self::TransmitBuffer => 2,
+            Self::BothBuffers => 3,
+            Self::Unknown(byte) => byte,
+        }
+    }
+}
+
+impl LineState {
+    pub const DATA_READY: Self = Self(0x01);
+    pub const OVERRUN_ERROR: Self = Self(0x02);
+    pub const PARITY_ERROR: Self = Self(0x04);
+    pub const FRAMING_ERROR: Self = Self(0x08);
+    pub const BREAK_DETECT: Self = Self(0x10);
+    pub const TRANSFER_HOLDING_REGISTER_EMPTY: Self = Self(0x20);
+    pub const TRANSFER_SHIFT_REGISTER_EMPTY: Self = Self(0x40);
+    pub const TIMEOUT_ERROR: Self = Self(0x80);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn all() -> Self {
+        Self(0xFF)
+    }
+
+    pub const fn from_u8(byte: u8) -> Self {
+        Self(byte)
+    }
+
+    pub const fn to_u8(self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl ModemState {
+    pub const DELTA_CTS: Self = Self(0x01);
+    pub const DELTA_DSR: Self = Self(0x02);
+    pub const TRAILING_EDGE_RI: Self = Self(0x04);
+    pub const DELTA_CD: Self = Self(0x08);
+    pub const CTS: Self = Self(0x10);
+    pub const DSR: Self = Self(0x20);
+    pub const RI: Self = Self(0x40);
+    pub const CD: Self = Self(0x80);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn all() -> Self {
+        Self(0xFF)
+    }
+
+    pub const fn from_u8(byte: u8) -> Self {
+        Self(byte)
+    }
+
+    pub const fn to_u8(self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+macro_rules! impl_wire_conversions {
+    ($($type:ty),*) => {
+        $(
+            impl From<u8> for $type {
+                fn from(byte: u8) -> Self {
+                    Self::from_u8(byte)
+                }
+            }
+
+            impl From<$type> for u8 {
+                fn from(value: $type) -> Self {
+                    value.to_u8()
+                }
+            }
+        )*
+    };
+}
+
+impl_wire_conversions!(Parity, StopBits, Control, Purge, LineState, ModemState);
+
+macro_rules! impl_bit_ops {
+    ($($type:ident),*) => {
+        $(
+            impl BitOr for $type {
+                type Output = Self;
+
+                fn bitor(self, rhs: Self) -> Self {
+                    Self(self.0 | rhs.0)
+                }
+            }
+
+            impl BitOrAssign for $type {
+                fn bitor_assign(&mut self, rhs: Self) {
+                    self.0 |= rhs.0;
+                }
+            }
+
+            impl BitAnd for $type {
+                type Output = Self;
+
+                fn bitand(self, rhs: Self) -> Self {
+                    Self(self.0 & rhs.0)
+                }
+            }
+        )*
+    };
+}
+
+impl_bit_ops!(LineState, ModemState);
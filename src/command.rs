@@ -2,6 +2,12 @@ use crate::codes;
 
 pub const SIZE: usize = 2;
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error {
+    WrongSize(usize),
+    MissingIac,
+}
+
 // Telnet commands without the ones related to negotiation and subnegotiation,
 // defined here: https://www.rfc-editor.org/rfc/rfc854.txt
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -36,8 +42,20 @@ impl Command {
     }
 
     pub const fn deserialize(buf: &[u8]) -> Self {
-        assert!(buf[0] == codes::IAC);
-        match buf[1] {
+        match Self::try_deserialize(buf) {
+            Ok(command) => command,
+            Err(_) => panic!("Not a valid command"),
+        }
+    }
+
+    pub const fn try_deserialize(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() != SIZE {
+            return Err(Error::WrongSize(buf.len()));
+        }
+        if buf[0] != codes::IAC {
+            return Err(Error::MissingIac);
+        }
+        Ok(match buf[1] {
             241 => Self::NoOp,
             242 => Self::DataMark,
             243 => Self::Break,
@@ -48,6 +66,6 @@ impl Command {
             248 => Self::EraseLine,
             249 => Self::GoAhead,
             _ => Self::Unsupported(buf[1]),
-        }
+        })
     }
 }
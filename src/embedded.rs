@@ -0,0 +1,290 @@
+use crate::backend::SerialBackend;
+use crate::handler::ServerHandler;
+use crate::session::{self, Session, MAX_ANSWER_SIZE};
+use core::time::Duration;
+use embedded_io::{Read, ReadReady, Write};
+
+const DATA_BUF_SIZE: usize = 256;
+
+// Errors of the drivers running a server `Session` over `embedded-io` network connections,
+// for targets without `std` and without an allocator
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error<N, S> {
+    Network(N),
+    Serial(S),
+    // The client closed the connection, the session is reset and the
+    // default port settings restored before this is returned
+    Disconnected,
+    // The session needed more room than the driver buffers offer
+    BufferTooSmall,
+    // The serial port accepted none of the data, like `ErrorKind::WriteZero` in `embedded_io`
+    WriteZero,
+}
+
+impl<N, S> From<session::Error<S>> for Error<N, S> {
+    fn from(error: session::Error<S>) -> Self {
+        match error {
+            session::Error::Serial(error) => Error::Serial(error),
+            session::Error::BufferTooSmall => Error::BufferTooSmall,
+        }
+    }
+}
+
+// Bridges the data between the network connection and the serial port without blocking
+// when no data is available, it is meant to be called repeatedly from the main loop.
+// `Session::connect` has to be called when a new client connects.
+// The driver has no clock, so it returns how long a break the client started with the
+// Telnet BREAK command lasts, the caller ends it with `Session::end_timed_break` once
+// that time has passed.
+#[must_use = "a break the client started lasts until `Session::end_timed_break` is called"]
+pub fn run_once<N, B, H>(
+    session: &mut Session<H>,
+    network: &mut N,
+    port: &mut B,
+) -> Result<Option<Duration>, Error<N::Error, B::Error>>
+where
+    N: Read + ReadReady + Write,
+    B: SerialBackend + ?Sized,
+    H: ServerHandler,
+{
+    let mut data = [0; DATA_BUF_SIZE];
+    let mut serial_out = [0; DATA_BUF_SIZE];
+    let mut network_out = [0; MAX_ANSWER_SIZE];
+
+    // Read and handle the data from the network connection
+    if network.read_ready().map_err(Error::Network)? {
+        let bytes_read = network.read(&mut data).map_err(Error::Network)?;
+        if bytes_read == 0 {
+            session.disconnect(port).map_err(Error::Serial)?;
+            return Err(Error::Disconnected);
+        }
+        let mut input = &data[..bytes_read];
+        while !input.is_empty() {
+            let progress =
+                session.process_network_data(port, input, &mut serial_out, &mut network_out)?;
+            write_all_serial(port, &serial_out[..progress.serial_produced])?;
+            network
+                .write_all(&network_out[..progress.network_produced])
+                .map_err(Error::Network)?;
+            input = &input[progress.consumed..];
+        }
+    }
+
+    // Read and handle the data from the serial port, unless the client suspended it
+    if !session.is_output_suspended() {
+        let bytes_read = port.read(&mut data).map_err(Error::Serial)?;
+        let mut input = &data[..bytes_read];
+        while !input.is_empty() {
+            let progress = session.process_serial_data(input, &mut network_out);
+            network
+                .write_all(&network_out[..progress.network_produced])
+                .map_err(Error::Network)?;
+            input = &input[progress.consumed..];
+        }
+    }
+
+    // Notify the client about the line and modem state changes
+    let size = session.process_port_state(port, &mut network_out)?;
+    network
+        .write_all(&network_out[..size])
+        .map_err(Error::Network)?;
+
+    network.flush().map_err(Error::Network)?;
+    Ok(session.take_break_timer())
+}
+
+// Writes all the data to the serial port without waiting for it to be transmitted,
+// the data left in the output buffer is what FLOWCONTROL-SUSPEND is based on.
+// A port taking none of the data is an error.
+fn write_all_serial<N, B: SerialBackend + ?Sized>(
+    port: &mut B,
+    mut data: &[u8],
+) -> Result<(), Error<N, B::Error>> {
+    while !data.is_empty() {
+        match port.write(data).map_err(Error::Serial)? {
+            0 => return Err(Error::WriteZero),
+            bytes_written => data = &data[bytes_written..],
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "embedded-io-async")]
+mod asynch {
+    use super::{Error, DATA_BUF_SIZE};
+    use crate::backend::SerialBackend;
+    use crate::handler::ServerHandler;
+    use crate::session::{Session, MAX_ANSWER_SIZE};
+    use core::cell::Cell;
+    use core::future::{poll_fn, Future};
+    use core::pin::pin;
+    use core::task::Poll;
+    use core::time::Duration;
+    use embedded_hal_async::delay::DelayNs;
+    use embedded_io::ErrorType;
+    use embedded_io_async::{Read, Write};
+
+    // How finely the time a break lasts is measured, and how often the serial output
+    // is checked on while it is backlogged
+    const TICK: Duration = Duration::from_millis(10);
+
+    enum Received<N, S> {
+        Network(Result<usize, N>),
+        Serial(Result<usize, S>),
+        Tick,
+    }
+
+    // Returns pending once without asking to be woken up, the driver polls again
+    // when it comes around
+    async fn pending_once() {
+        let mut polled = false;
+        poll_fn(|_| match polled {
+            true => Poll::Ready(()),
+            false => {
+                polled = true;
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    // Bridges the data between the network connection and the serial port until the client
+    // disconnects, after which the session is reset and the default port settings restored.
+    // The serial data is read through `embedded_io_async::Read` and written through
+    // `embedded_io_async::Write`, the `SerialBackend` is only used for the port settings.
+    // The writes are not flushed, as the data waiting in the port is what decides whether
+    // the client is asked to suspend, so they have to reach the UART on their own.
+    // Pending reads are dropped once the other side receives data, so both have to be
+    // cancel safe. The line and modem state is checked whenever either side receives data,
+    // and every tick of `delay` while the client is asked to suspend sending data.
+    // A break started by the Telnet BREAK command is timed with `delay` while the data
+    // keeps flowing, and ended once the configured duration has passed.
+    pub async fn run<N, B, D, H>(
+        session: &mut Session<H>,
+        network: &mut N,
+        port: &mut B,
+        delay: &mut D,
+    ) -> Result<(), Error<N::Error, <B as SerialBackend>::Error>>
+    where
+        N: Read + Write,
+        B: SerialBackend + Read + Write + ErrorType<Error = <B as SerialBackend>::Error>,
+        D: DelayNs,
+        H: ServerHandler,
+    {
+        let mut network_data = [0; DATA_BUF_SIZE];
+        let mut port_data = [0; DATA_BUF_SIZE];
+        let mut serial_out = [0; DATA_BUF_SIZE];
+        let mut network_out = [0; MAX_ANSWER_SIZE];
+        // How long the break in progress still lasts
+        let mut break_left: Option<Duration> = None;
+
+        // Measures the time in ticks while there is something to time, the ticker outlives
+        // the reads dropped on every turn so no time goes unaccounted
+        let ticking = Cell::new(false);
+        let ticked = Cell::new(false);
+        let mut ticker = pin!(async {
+            loop {
+                if ticking.get() && !ticked.get() {
+                    delay.delay_ms(TICK.as_millis() as u32).await;
+                    ticked.set(true);
+                } else {
+                    pending_once().await;
+                }
+            }
+        });
+
+        session.connect();
+        loop {
+            let received = {
+                // The serial port is left alone while the client suspended its data
+                let port_suspended = session.is_output_suspended();
+                let mut network_read = pin!(network.read(&mut network_data));
+                let mut port_read = pin!(Read::read(port, &mut port_data));
+                poll_fn(|cx| {
+                    // The ticker never finishes, it only marks the ticks
+                    let _ = ticker.as_mut().poll(cx);
+                    if ticked.get() {
+                        return Poll::Ready(Received::Tick);
+                    }
+                    if let Poll::Ready(result) = network_read.as_mut().poll(cx) {
+                        return Poll::Ready(Received::Network(result));
+                    }
+                    if !port_suspended {
+                        if let Poll::Ready(result) = port_read.as_mut().poll(cx) {
+                            return Poll::Ready(Received::Serial(result));
+                        }
+                    }
+                    Poll::Pending
+                })
+                .await
+            };
+
+            match received {
+                Received::Network(result) => {
+                    let bytes_read = result.map_err(Error::Network)?;
+                    if bytes_read == 0 {
+                        session.disconnect(port).map_err(Error::Serial)?;
+                        return Ok(());
+                    }
+                    let mut input = &network_data[..bytes_read];
+                    while !input.is_empty() {
+                        let progress = session.process_network_data(
+                            port,
+                            input,
+                            &mut serial_out,
+                            &mut network_out,
+                        )?;
+                        Write::write_all(port, &serial_out[..progress.serial_produced])
+                            .await
+                            .map_err(Error::Serial)?;
+                        network
+                            .write_all(&network_out[..progress.network_produced])
+                            .await
+                            .map_err(Error::Network)?;
+                        input = &input[progress.consumed..];
+                    }
+                    if let Some(duration) = session.take_break_timer() {
+                        break_left = Some(duration);
+                    }
+                }
+                Received::Serial(result) => {
+                    let bytes_read = result.map_err(Error::Serial)?;
+                    let mut input = &port_data[..bytes_read];
+                    while !input.is_empty() {
+                        let progress = session.process_serial_data(input, &mut network_out);
+                        network
+                            .write_all(&network_out[..progress.network_produced])
+                            .await
+                            .map_err(Error::Network)?;
+                        input = &input[progress.consumed..];
+                    }
+                }
+                Received::Tick => {
+                    ticked.set(false);
+                    if let Some(left) = break_left {
+                        let left = left.saturating_sub(TICK);
+                        break_left = (!left.is_zero()).then_some(left);
+                        if break_left.is_none() {
+                            session.end_timed_break(port).map_err(Error::Serial)?;
+                        }
+                    }
+                }
+            }
+
+            // Notify the client about the line and modem state changes
+            let size = session.process_port_state(port, &mut network_out)?;
+            network
+                .write_all(&network_out[..size])
+                .await
+                .map_err(Error::Network)?;
+            network.flush().await.map_err(Error::Network)?;
+
+            // Nothing signals the serial output draining, so it is checked on every tick
+            // to ask the client to resume
+            ticking.set(break_left.is_some() || session.is_input_suspended());
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+pub use asynch::run;
@@ -0,0 +1,67 @@
+use crate::{codes, command, negotiation, Command, Negotiation, Subnegotiation};
+
+// The output buffer cannot hold a command, negotiation or subnegotiation,
+// which are only ever written whole while data is escaped as far as the buffer allows
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error {
+    BufferTooSmall { required: usize },
+}
+
+// How much of the input was encoded and how many bytes that produced
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Encoded {
+    pub consumed: usize,
+    pub produced: usize,
+}
+
+// Escapes the data by doubling all IAC bytes, stopping once the output runs out of space
+pub fn encode_data(input: &[u8], out: &mut [u8]) -> Encoded {
+    let mut encoded = Encoded::default();
+    for &byte in input {
+        let size = if byte == codes::IAC { 2 } else { 1 };
+        if out.len() - encoded.produced < size {
+            break;
+        }
+        out[encoded.produced..encoded.produced + size].fill(byte);
+        encoded.produced += size;
+        encoded.consumed += 1;
+    }
+    encoded
+}
+
+pub fn encode_command(command: Command, out: &mut [u8]) -> Result<usize, Error> {
+    check_size(out, command::SIZE)?;
+    command.serialize(out);
+    Ok(command::SIZE)
+}
+
+pub fn encode_negotiation(negotiation: Negotiation, out: &mut [u8]) -> Result<usize, Error> {
+    check_size(out, negotiation::SIZE)?;
+    negotiation.serialize(out);
+    Ok(negotiation::SIZE)
+}
+
+// Encodes a subnegotiation sent by the client, with IAC bytes escaped in its data
+pub fn encode_client_subnegotiation(
+    subnegotiation: &Subnegotiation,
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    check_size(out, subnegotiation.serialized_size())?;
+    Ok(subnegotiation.serialize_client(out))
+}
+
+// Encodes a subnegotiation sent by the server, with IAC bytes escaped in its data
+pub fn encode_server_subnegotiation(
+    subnegotiation: &Subnegotiation,
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    check_size(out, subnegotiation.serialized_size())?;
+    Ok(subnegotiation.serialize_server(out))
+}
+
+fn check_size(out: &[u8], required: usize) -> Result<(), Error> {
+    match out.len() < required {
+        true => Err(Error::BufferTooSmall { required }),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,72 @@
+use crate::com_port::{Control, Purge};
+
+// Which way data passes through the server
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    ToSerial,
+    ToNetwork,
+}
+
+// Hooks the server calls as it handles a client, letting the embedding application follow
+// along and change or refuse the client's requests before they reach the serial port.
+// Returning `None` refuses a request and the client is answered with the current state,
+// returning a different value applies that one instead. The default methods change nothing.
+pub trait ServerHandler {
+    fn on_connect(&mut self) {}
+
+    fn on_disconnect(&mut self) {}
+
+    fn on_baud_rate(&mut self, baud_rate: u32) -> Option<u32> {
+        Some(baud_rate)
+    }
+
+    // Called for the DTR, RTS and flow control changes, queries are answered directly.
+    // Serial ports have no flow control for one direction only or through DCD or DSR,
+    // those requests are answered with the flow control the port uses, whatever is returned.
+    fn on_control(&mut self, control: Control) -> Option<Control> {
+        Some(control)
+    }
+
+    fn on_purge(&mut self, purge: Purge) -> Option<Purge> {
+        Some(purge)
+    }
+
+    // Called with true when the break state is set and with false when it is cleared
+    fn on_break(&mut self, break_state: bool) -> Option<bool> {
+        Some(break_state)
+    }
+
+    fn on_data(&mut self, _direction: Direction, _data: &[u8]) {}
+}
+
+impl ServerHandler for () {}
+
+impl<H: ServerHandler + ?Sized> ServerHandler for &mut H {
+    fn on_connect(&mut self) {
+        (**self).on_connect()
+    }
+
+    fn on_disconnect(&mut self) {
+        (**self).on_disconnect()
+    }
+
+    fn on_baud_rate(&mut self, baud_rate: u32) -> Option<u32> {
+        (**self).on_baud_rate(baud_rate)
+    }
+
+    fn on_control(&mut self, control: Control) -> Option<Control> {
+        (**self).on_control(control)
+    }
+
+    fn on_purge(&mut self, purge: Purge) -> Option<Purge> {
+        (**self).on_purge(purge)
+    }
+
+    fn on_break(&mut self, break_state: bool) -> Option<bool> {
+        (**self).on_break(break_state)
+    }
+
+    fn on_data(&mut self, direction: Direction, data: &[u8]) {
+        (**self).on_data(direction, data)
+    }
+}
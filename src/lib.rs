@@ -1,19 +1,48 @@
 #![cfg_attr(not(any(test, feature = "std")), no_std)]
 
+#[macro_use]
+mod logging;
+
+#[cfg(feature = "std")]
+pub mod access;
+#[cfg(feature = "tokio")]
+pub mod async_server;
+pub mod backend;
+#[cfg(feature = "std")]
+pub mod client;
 mod codes;
+pub mod com_port;
 pub mod command;
+#[cfg(feature = "embedded-io")]
+pub mod embedded;
+pub mod encoder;
+pub mod handler;
+#[cfg(feature = "std")]
+pub mod multi_server;
 pub mod negotiation;
 pub mod parser;
+pub mod policy;
+#[cfg(feature = "std")]
+pub mod selector;
 #[cfg(feature = "std")]
 mod serialport_conversions;
 #[cfg(feature = "std")]
 pub mod server;
+pub mod session;
 pub mod subnegotiation;
 
 // Public API
+#[cfg(feature = "tokio")]
+pub use async_server::AsyncServer;
+#[cfg(feature = "std")]
+pub use client::Client;
 pub use command::Command;
+pub use handler::ServerHandler;
+#[cfg(feature = "std")]
+pub use multi_server::MultiServer;
 pub use negotiation::Negotiation;
 pub use parser::Parser;
 #[cfg(feature = "std")]
-pub use server::Server;
+pub use server::{Server, ServerBuilder};
+pub use session::Session;
 pub use subnegotiation::Subnegotiation;
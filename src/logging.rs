@@ -0,0 +1,37 @@
+// Logging macros forwarding to `log` and `tracing`, each behind its own feature.
+// Without either they compile to nothing, the arguments are type checked but never evaluated.
+
+macro_rules! log_event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::$level!($($arg)+);
+        #[cfg(feature = "log")]
+        ::log::$level!($($arg)+);
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+// Only the std servers have errors to report that are not returned to the caller
+#[allow(unused_macros)]
+macro_rules! error {
+    ($($arg:tt)+) => { log_event!(error, $($arg)+) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => { log_event!(warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { log_event!(info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { log_event!(debug, $($arg)+) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)+) => { log_event!(trace, $($arg)+) };
+}
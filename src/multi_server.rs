@@ -0,0 +1,96 @@
+use crate::backend::SerialBackend;
+use crate::handler::ServerHandler;
+use crate::server::{Error, Server, ServerBuilder};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+
+// Serves several serial ports from one process, each bridge on its own TCP port.
+// Every bridge runs on its own thread, so a failing device stops only its own bridge.
+#[derive(Default)]
+pub struct MultiServer {
+    bridges: Vec<Bridge>,
+}
+
+struct Bridge {
+    name: String,
+    // Runs the bridge until it fails
+    run: Box<dyn FnOnce() -> Error + Send>,
+}
+
+impl MultiServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a bridge serving the device on the address, the port is opened once `run` is called
+    pub fn add<H>(mut self, builder: ServerBuilder<H>, device: &str, tcp_addr: SocketAddr) -> Self
+    where
+        H: ServerHandler + Send + 'static,
+    {
+        let name = device.to_owned();
+        self.bridges.push(Bridge {
+            name: name.clone(),
+            run: Box::new(move || match builder.build(&name, tcp_addr) {
+                Ok(mut server) => run_server(&mut server),
+                Err(error) => error,
+            }),
+        });
+        self
+    }
+
+    // Adds an already built server, the name identifies it in the errors
+    pub fn add_server<B, H>(mut self, name: &str, mut server: Server<B, H>) -> Self
+    where
+        B: SerialBackend + Send + ?Sized + 'static,
+        H: ServerHandler + Send + 'static,
+        io::Error: From<B::Error>,
+    {
+        self.bridges.push(Bridge {
+            name: name.to_owned(),
+            run: Box::new(move || run_server(&mut server)),
+        });
+        self
+    }
+
+    // Runs every bridge until all of them stopped, calling `on_error` as each one fails,
+    // including the bridges whose thread could not be started
+    pub fn run(self, mut on_error: impl FnMut(&str, Error)) {
+        let (sender, receiver) = mpsc::channel();
+        for bridge in self.bridges {
+            let sender = sender.clone();
+            let name = bridge.name.clone();
+            // Naming the thread after the device tells the bridges apart in logs
+            let spawned = thread::Builder::new()
+                .name(bridge.name.clone())
+                .spawn(move || {
+                    let error = (bridge.run)();
+                    let _ = sender.send((bridge.name, error));
+                });
+            if let Err(error) = spawned {
+                on_error(&name, Error::Thread(error));
+            }
+        }
+        // The loop ends once every bridge thread dropped its sender
+        drop(sender);
+        for (name, error) in receiver {
+            on_error(&name, error);
+        }
+    }
+}
+
+// A client whose connection fails is only dropped by `Server::run`, so the bridge stops
+// on the errors of the serial port or the listener
+fn run_server<B, H>(server: &mut Server<B, H>) -> Error
+where
+    B: SerialBackend + ?Sized,
+    H: ServerHandler,
+    io::Error: From<B::Error>,
+{
+    loop {
+        if let Err(error) = server.run() {
+            return error;
+        }
+    }
+}
@@ -2,6 +2,13 @@ use crate::codes;
 
 pub const SIZE: usize = 3;
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error {
+    WrongSize(usize),
+    MissingIac,
+    UnknownIntent(u8),
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Negotiation {
     pub intent: Intent,
@@ -34,12 +41,28 @@ impl Negotiation {
     }
 
     pub const fn deserialize(buf: &[u8]) -> Self {
-        assert!(buf[0] == codes::IAC);
-        Self {
-            intent: Intent::from_u8(buf[1]),
-            option: Option::from_u8(buf[2]),
+        match Self::try_deserialize(buf) {
+            Ok(negotiation) => negotiation,
+            Err(_) => panic!("Not a valid negotiation"),
         }
     }
+
+    pub const fn try_deserialize(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() != SIZE {
+            return Err(Error::WrongSize(buf.len()));
+        }
+        if buf[0] != codes::IAC {
+            return Err(Error::MissingIac);
+        }
+        let intent = match Intent::from_u8(buf[1]) {
+            Some(intent) => intent,
+            None => return Err(Error::UnknownIntent(buf[1])),
+        };
+        Ok(Self {
+            intent,
+            option: Option::from_u8(buf[2]),
+        })
+    }
 }
 
 impl Option {
@@ -65,13 +88,13 @@ impl Option {
 }
 
 impl Intent {
-    const fn from_u8(byte: u8) -> Intent {
+    const fn from_u8(byte: u8) -> core::option::Option<Intent> {
         match byte {
-            codes::WILL => Self::Will,
-            codes::WONT => Self::Wont,
-            codes::DO => Self::Do,
-            codes::DONT => Self::Dont,
-            _ => panic!("Not a command code for negotiation intent"),
+            codes::WILL => Some(Self::Will),
+            codes::WONT => Some(Self::Wont),
+            codes::DO => Some(Self::Do),
+            codes::DONT => Some(Self::Dont),
+            _ => None,
         }
     }
 
@@ -84,3 +107,199 @@ impl Intent {
         }
     }
 }
+
+// Option negotiation state of one side of the connection, as defined by the
+// RFC 1143 "Q method": https://www.rfc-editor.org/rfc/rfc1143.txt
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OptionState {
+    No,
+    Yes,
+    WantNo(Queue),
+    WantYes(Queue),
+}
+
+// Whether the opposite of the pending request should be requested once it is answered
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Queue {
+    Empty,
+    Opposite,
+}
+
+// Local options are the ones we perform (WILL/WONT),
+// remote options are the ones the other side performs (DO/DONT)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Change {
+    Enabled(Side, Option),
+    Disabled(Side, Option),
+}
+
+// The negotiation to send, if any, and how it changed whether the option is used
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Reply {
+    pub answer: core::option::Option<Negotiation>,
+    pub change: core::option::Option<Change>,
+}
+
+pub struct OptionTable {
+    local: [OptionState; 256],
+    remote: [OptionState; 256],
+    local_supported: [bool; 256],
+    remote_supported: [bool; 256],
+}
+
+impl Default for OptionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OptionTable {
+    pub const fn new() -> Self {
+        Self {
+            local: [OptionState::No; 256],
+            remote: [OptionState::No; 256],
+            local_supported: [false; 256],
+            remote_supported: [false; 256],
+        }
+    }
+
+    // Allows the other side to enable the option on the given side,
+    // unsupported options are always refused
+    pub const fn support(&mut self, side: Side, option: Option) {
+        match side {
+            Side::Local => self.local_supported[option.to_u8() as usize] = true,
+            Side::Remote => self.remote_supported[option.to_u8() as usize] = true,
+        }
+    }
+
+    pub fn state(&self, side: Side, option: Option) -> OptionState {
+        match side {
+            Side::Local => self.local[option.to_u8() as usize],
+            Side::Remote => self.remote[option.to_u8() as usize],
+        }
+    }
+
+    pub fn is_enabled(&self, side: Side, option: Option) -> bool {
+        self.state(side, option) == OptionState::Yes
+    }
+
+    // Decides the reply to a negotiation received from the other side
+    pub fn receive(&mut self, negotiation: Negotiation) -> Reply {
+        let option = negotiation.option;
+        let (side, enable) = match negotiation.intent {
+            Intent::Will => (Side::Remote, true),
+            Intent::Wont => (Side::Remote, false),
+            Intent::Do => (Side::Local, true),
+            Intent::Dont => (Side::Local, false),
+        };
+        let supported = match side {
+            Side::Local => self.local_supported[option.to_u8() as usize],
+            Side::Remote => self.remote_supported[option.to_u8() as usize],
+        };
+        let state = self.state(side, option);
+
+        let (new_state, answer) = match (enable, state) {
+            (true, OptionState::No) if supported => (OptionState::Yes, Some(true)),
+            (true, OptionState::No) => (OptionState::No, Some(false)),
+            (true, OptionState::Yes) => (OptionState::Yes, None),
+            // The other side answered our disable request with an enable, which is an error
+            (true, OptionState::WantNo(Queue::Empty)) => (OptionState::No, None),
+            (true, OptionState::WantNo(Queue::Opposite)) => (OptionState::Yes, None),
+            (true, OptionState::WantYes(Queue::Empty)) => (OptionState::Yes, None),
+            (true, OptionState::WantYes(Queue::Opposite)) => {
+                (OptionState::WantNo(Queue::Empty), Some(false))
+            }
+            (false, OptionState::No) => (OptionState::No, None),
+            (false, OptionState::Yes) => (OptionState::No, Some(false)),
+            (false, OptionState::WantNo(Queue::Empty)) => (OptionState::No, None),
+            (false, OptionState::WantNo(Queue::Opposite)) => {
+                (OptionState::WantYes(Queue::Empty), Some(true))
+            }
+            (false, OptionState::WantYes(_)) => (OptionState::No, None),
+        };
+        self.set_state(side, option, new_state);
+
+        let change = match (state, new_state) {
+            (OptionState::Yes, OptionState::Yes) => None,
+            (_, OptionState::Yes) => Some(Change::Enabled(side, option)),
+            (OptionState::Yes, _) => Some(Change::Disabled(side, option)),
+            _ => None,
+        };
+
+        Reply {
+            answer: answer.map(|enable| Self::negotiation(side, option, enable)),
+            change,
+        }
+    }
+
+    // Asks the other side to enable the option on the given side,
+    // returning the negotiation to send if one is needed
+    pub fn request_enable(
+        &mut self,
+        side: Side,
+        option: Option,
+    ) -> core::option::Option<Negotiation> {
+        match self.state(side, option) {
+            OptionState::No => {
+                self.set_state(side, option, OptionState::WantYes(Queue::Empty));
+                Some(Self::negotiation(side, option, true))
+            }
+            OptionState::WantNo(Queue::Empty) => {
+                self.set_state(side, option, OptionState::WantNo(Queue::Opposite));
+                None
+            }
+            OptionState::WantYes(Queue::Opposite) => {
+                self.set_state(side, option, OptionState::WantYes(Queue::Empty));
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // Asks the other side to disable the option on the given side, returning the
+    // negotiation to send if one is needed. An enabled option stops being used right away,
+    // so its change is reported here rather than once the other side agrees.
+    pub fn request_disable(&mut self, side: Side, option: Option) -> Reply {
+        match self.state(side, option) {
+            OptionState::Yes => {
+                self.set_state(side, option, OptionState::WantNo(Queue::Empty));
+                Reply {
+                    answer: Some(Self::negotiation(side, option, false)),
+                    change: Some(Change::Disabled(side, option)),
+                }
+            }
+            OptionState::WantNo(Queue::Opposite) => {
+                self.set_state(side, option, OptionState::WantNo(Queue::Empty));
+                Reply::default()
+            }
+            OptionState::WantYes(Queue::Empty) => {
+                self.set_state(side, option, OptionState::WantYes(Queue::Opposite));
+                Reply::default()
+            }
+            _ => Reply::default(),
+        }
+    }
+
+    fn set_state(&mut self, side: Side, option: Option, state: OptionState) {
+        match side {
+            Side::Local => self.local[option.to_u8() as usize] = state,
+            Side::Remote => self.remote[option.to_u8() as usize] = state,
+        }
+    }
+
+    const fn negotiation(side: Side, option: Option, enable: bool) -> Negotiation {
+        let intent = match (side, enable) {
+            (Side::Local, true) => Intent::Will,
+            (Side::Local, false) => Intent::Wont,
+            (Side::Remote, true) => Intent::Do,
+            (Side::Remote, false) => Intent::Dont,
+        };
+        Negotiation { intent, option }
+    }
+}
@@ -1,8 +1,11 @@
 use crate::{codes, command, negotiation, subnegotiation, Command, Negotiation, Subnegotiation};
 
+// Events are generic over their data, which is a single byte when processing byte by byte
+// and a run of contiguous data bytes when feeding slices
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum Event {
-    Data(u8),
+#[allow(clippy::large_enum_variant)]
+pub enum Event<D = u8> {
+    Data(D),
     Command(Command),
     Negotiation(Negotiation),
     Subnegotiation(Subnegotiation),
@@ -12,6 +15,9 @@ pub enum Event {
 pub enum Error {
     SubnegotiationParsing,
     BufferOverflow,
+    Command(command::Error),
+    Negotiation(negotiation::Error),
+    Subnegotiation(subnegotiation::Error),
 }
 
 enum State {
@@ -24,12 +30,24 @@ enum State {
     SubnegotiationEnd,
 }
 
+// Iterator over the events in a slice fed to the parser
+pub struct Feed<'p, 'a> {
+    parser: &'p mut Parser,
+    input: &'a [u8],
+}
+
 pub struct Parser {
     state: State,
     buf: [u8; subnegotiation::MAX_SIZE],
     buf_cnt: usize,
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
     pub const fn new() -> Self {
         Self {
@@ -43,7 +61,26 @@ impl Parser {
         *self = Self::new();
     }
 
+    // Processes a slice received from the network, yielding the unescaped data
+    // in runs borrowed from the input, interleaved with the other events
+    pub fn feed<'a>(&mut self, input: &'a [u8]) -> Feed<'_, 'a> {
+        Feed {
+            parser: self,
+            input,
+        }
+    }
+
+    // Processes a single byte received from the network, after an error
+    // the parser continues from the data state with the following byte
     pub fn process_byte(&mut self, byte: u8) -> Result<Option<Event>, Error> {
+        let result = self.process_byte_in_state(byte);
+        if result.is_err() {
+            self.state = State::Data;
+        }
+        result
+    }
+
+    fn process_byte_in_state(&mut self, byte: u8) -> Result<Option<Event>, Error> {
         match self.state {
             State::Data => {
                 if byte == codes::IAC {
@@ -61,15 +98,15 @@ impl Parser {
                 self.buf_cnt = 0;
                 self.write_to_buf(codes::IAC)?;
                 self.write_to_buf(byte)?;
-                Ok(self.process_command_byte(byte))
+                self.process_command_byte(byte)
             }
 
             State::Negotiation => {
                 self.write_to_buf(byte)?;
                 self.state = State::Data;
-                Ok(Some(Event::Negotiation(Negotiation::deserialize(
-                    &self.buf[..negotiation::SIZE],
-                ))))
+                let negotiation = Negotiation::try_deserialize(&self.buf[..negotiation::SIZE])
+                    .map_err(Error::Negotiation)?;
+                Ok(Some(Event::Negotiation(negotiation)))
             }
 
             State::SubnegotiationOption => {
@@ -103,9 +140,10 @@ impl Parser {
                     codes::SE => {
                         self.write_to_buf(byte)?;
                         self.state = State::Data;
-                        Ok(Some(Event::Subnegotiation(Subnegotiation::deserialize(
-                            &self.buf[..self.buf_cnt],
-                        ))))
+                        let subnegotiation =
+                            Subnegotiation::try_deserialize(&self.buf[..self.buf_cnt])
+                                .map_err(Error::Subnegotiation)?;
+                        Ok(Some(Event::Subnegotiation(subnegotiation)))
                     }
                     _ => Err(Error::SubnegotiationParsing),
                 }
@@ -113,21 +151,21 @@ impl Parser {
         }
     }
 
-    fn process_command_byte(&mut self, command_code: u8) -> Option<Event> {
+    fn process_command_byte(&mut self, command_code: u8) -> Result<Option<Event>, Error> {
         match command_code {
             codes::WILL | codes::WONT | codes::DO | codes::DONT => {
                 self.state = State::Negotiation;
-                None
+                Ok(None)
             }
             codes::SB => {
                 self.state = State::SubnegotiationOption;
-                None
+                Ok(None)
             }
             _ => {
                 self.state = State::Data;
-                Some(Event::Command(Command::deserialize(
-                    &self.buf[..command::SIZE],
-                )))
+                let command =
+                    Command::try_deserialize(&self.buf[..command::SIZE]).map_err(Error::Command)?;
+                Ok(Some(Event::Command(command)))
             }
         }
     }
@@ -141,3 +179,49 @@ impl Parser {
         Ok(())
     }
 }
+
+impl<'a> Feed<'_, 'a> {
+    // The part of the input that has not been processed yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.input
+    }
+}
+
+impl<'a> Iterator for Feed<'_, 'a> {
+    type Item = Result<Event<&'a [u8]>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Plain data is passed through up to the next IAC
+        if matches!(self.parser.state, State::Data) {
+            let run = self
+                .input
+                .iter()
+                .position(|&byte| byte == codes::IAC)
+                .unwrap_or(self.input.len());
+            if run > 0 {
+                let (data, rest) = self.input.split_at(run);
+                self.input = rest;
+                return Some(Ok(Event::Data(data)));
+            }
+        }
+
+        while let Some((byte, rest)) = self.input.split_first() {
+            let consumed = &self.input[..1];
+            self.input = rest;
+            match self.parser.process_byte(*byte) {
+                Ok(None) => continue,
+                // An escaped IAC is the only data byte produced here
+                Ok(Some(Event::Data(_))) => return Some(Ok(Event::Data(consumed))),
+                Ok(Some(Event::Command(command))) => return Some(Ok(Event::Command(command))),
+                Ok(Some(Event::Negotiation(negotiation))) => {
+                    return Some(Ok(Event::Negotiation(negotiation)))
+                }
+                Ok(Some(Event::Subnegotiation(subnegotiation))) => {
+                    return Some(Ok(Event::Subnegotiation(subnegotiation)))
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        None
+    }
+}
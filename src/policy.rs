@@ -0,0 +1,78 @@
+use crate::backend::FlowControl;
+use crate::com_port::{Parity, StopBits};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BaudRatePolicy {
+    Any,
+    // Only the listed rates are applied, others are refused
+    Only(&'static [u32]),
+    // Rates outside the range are raised or lowered to its closest end
+    Clamp { min: u32, max: u32 },
+    // Rates are replaced with the closest listed one
+    Nearest(&'static [u32]),
+    // The client cannot change the rate
+    Locked,
+}
+
+impl BaudRatePolicy {
+    // The rate to apply for the requested one, if any
+    pub fn apply(&self, baud_rate: u32) -> Option<u32> {
+        match *self {
+            Self::Any => Some(baud_rate),
+            Self::Only(rates) => rates.contains(&baud_rate).then_some(baud_rate),
+            Self::Clamp { min, max } => Some(baud_rate.min(max).max(min)),
+            Self::Nearest(rates) => rates
+                .iter()
+                .copied()
+                .min_by_key(|rate| rate.abs_diff(baud_rate)),
+            Self::Locked => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Allowed<T: 'static> {
+    Any,
+    // Only the listed values are applied, others are refused
+    Only(&'static [T]),
+    // The client cannot change the setting
+    Locked,
+}
+
+impl<T: PartialEq> Allowed<T> {
+    pub fn permits(&self, value: &T) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Only(values) => values.contains(value),
+            Self::Locked => false,
+        }
+    }
+}
+
+// Limits on the line settings a client may change, checked before they reach the serial port.
+// Requests the policy refuses are answered with the setting currently in effect.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LinePolicy {
+    pub baud_rate: BaudRatePolicy,
+    pub data_bits: Allowed<u8>,
+    pub parity: Allowed<Parity>,
+    pub stop_bits: Allowed<StopBits>,
+    pub flow_control: Allowed<FlowControl>,
+}
+
+impl Default for LinePolicy {
+    fn default() -> Self {
+        Self::ANY
+    }
+}
+
+impl LinePolicy {
+    // Lets the client change every setting
+    pub const ANY: Self = LinePolicy {
+        baud_rate: BaudRatePolicy::Any,
+        data_bits: Allowed::Any,
+        parity: Allowed::Any,
+        stop_bits: Allowed::Any,
+        flow_control: Allowed::Any,
+    };
+}
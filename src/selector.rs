@@ -0,0 +1,147 @@
+use serialport::{SerialPortInfo, SerialPortType};
+use std::io;
+use std::num::ParseIntError;
+use std::path::Path;
+use std::str::FromStr;
+
+// Where udev links the serial ports under names made of their USB metadata
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+#[derive(Debug)]
+pub enum Error {
+    // A usb:VID:PID selector without both ids in hex
+    UsbId(String, Option<ParseIntError>),
+    Ports(serialport::Error),
+    ById(io::Error),
+    // No port matches the selector
+    NotFound,
+    // Several ports match the selector, with their names
+    Ambiguous(Vec<String>),
+}
+
+// The serial port to open, either by its path or by what identifies the device plugged in.
+// Selectors other than a path are looked up among the available ports every time the port
+// is opened, so they keep working when the device shows up under another path.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DeviceSelector {
+    Path(String),
+    // usb:303a:1001, the vendor and product ids in hex
+    Usb { vid: u16, pid: u16 },
+    // serial-number:ABC123, the serial number of a USB device
+    SerialNumber(String),
+    // by-id:usb-Espressif*, a glob matched against the names in /dev/serial/by-id,
+    // where '*' matches any characters and '?' a single one
+    ById(String),
+}
+
+impl DeviceSelector {
+    // The path of the only port matching the selector
+    pub fn resolve(&self) -> Result<String, Error> {
+        let mut names = match self {
+            DeviceSelector::Path(path) => return Ok(path.clone()),
+            DeviceSelector::Usb { .. } | DeviceSelector::SerialNumber(_) => {
+                serialport::available_ports()
+                    .map_err(Error::Ports)?
+                    .into_iter()
+                    .filter(|port| self.matches(port))
+                    .map(|port| port.port_name)
+                    .collect()
+            }
+            DeviceSelector::ById(glob) => by_id_links(glob)?,
+        };
+        match names.len() {
+            0 => Err(Error::NotFound),
+            1 => Ok(names.remove(0)),
+            _ => Err(Error::Ambiguous(names)),
+        }
+    }
+
+    // Whether the available port is the one selected, by-id globs match none of them
+    pub fn matches(&self, port: &SerialPortInfo) -> bool {
+        let usb = match &port.port_type {
+            SerialPortType::UsbPort(usb) => Some(usb),
+            _ => None,
+        };
+        match self {
+            DeviceSelector::Path(path) => port.port_name == *path,
+            DeviceSelector::Usb { vid, pid } => {
+                usb.is_some_and(|usb| usb.vid == *vid && usb.pid == *pid)
+            }
+            DeviceSelector::SerialNumber(serial_number) => {
+                usb.is_some_and(|usb| usb.serial_number.as_ref() == Some(serial_number))
+            }
+            DeviceSelector::ById(_) => false,
+        }
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = Error;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        if let Some(ids) = selector.strip_prefix("usb:") {
+            let invalid = |error| Error::UsbId(ids.to_owned(), error);
+            let (vid, pid) = ids.split_once(':').ok_or_else(|| invalid(None))?;
+            return Ok(DeviceSelector::Usb {
+                vid: u16::from_str_radix(vid, 16).map_err(|error| invalid(Some(error)))?,
+                pid: u16::from_str_radix(pid, 16).map_err(|error| invalid(Some(error)))?,
+            });
+        }
+        if let Some(serial_number) = selector.strip_prefix("serial-number:") {
+            return Ok(DeviceSelector::SerialNumber(serial_number.to_owned()));
+        }
+        if let Some(glob) = selector.strip_prefix("by-id:") {
+            return Ok(DeviceSelector::ById(glob.to_owned()));
+        }
+        Ok(DeviceSelector::Path(selector.to_owned()))
+    }
+}
+
+// The sorted paths of the by-id links whose names match the glob
+fn by_id_links(glob: &str) -> Result<Vec<String>, Error> {
+    let entries = match Path::new(BY_ID_DIR).read_dir() {
+        Ok(entries) => entries,
+        // The directory only exists while a USB serial port is plugged in
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(Error::ById(error)),
+    };
+    let mut links = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(Error::ById)?;
+        if let Some(name) = entry.file_name().to_str() {
+            if glob_matches(glob.as_bytes(), name.as_bytes()) {
+                links.push(entry.path().to_string_lossy().into_owned());
+            }
+        }
+    }
+    links.sort();
+    Ok(links)
+}
+
+fn glob_matches(glob: &[u8], name: &[u8]) -> bool {
+    let (mut glob_pos, mut name_pos) = (0, 0);
+    // Where the last '*' was and the name position it currently stands for
+    let mut backtrack = None;
+    while name_pos < name.len() {
+        match glob.get(glob_pos) {
+            Some(b'*') => {
+                backtrack = Some((glob_pos, name_pos));
+                glob_pos += 1;
+            }
+            Some(&c) if c == b'?' || c == name[name_pos] => {
+                glob_pos += 1;
+                name_pos += 1;
+            }
+            // Let the last '*' take one more character and try again
+            _ => match backtrack {
+                Some((star, star_name_pos)) => {
+                    backtrack = Some((star, star_name_pos + 1));
+                    glob_pos = star + 1;
+                    name_pos = star_name_pos + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[glob_pos..].iter().all(|&c| c == b'*')
+}
@@ -1,3 +1,4 @@
+use crate::com_port;
 use serialport::{DataBits, FlowControl, Parity, StopBits};
 
 // Required functions for conversions between serialport Enums and rfc2217 option values
@@ -20,51 +21,52 @@ pub(crate) const fn u8_to_data_bits(value: u8) -> Option<DataBits> {
     }
 }
 
-pub(crate) const fn parity_to_u8(parity: Parity) -> u8 {
+pub(crate) const fn parity_to_option(parity: Parity) -> com_port::Parity {
     match parity {
-        Parity::None => 1,
-        Parity::Odd => 2,
-        Parity::Even => 3,
+        Parity::None => com_port::Parity::None,
+        Parity::Odd => com_port::Parity::Odd,
+        Parity::Even => com_port::Parity::Even,
     }
 }
 
-pub(crate) const fn u8_to_parity(value: u8) -> Option<Parity> {
+// serialport has no mark and space parity, nor 1.5 stop bits, so those have no equivalent
+pub(crate) const fn option_to_parity(value: com_port::Parity) -> Option<Parity> {
     match value {
-        1 => Some(Parity::None),
-        2 => Some(Parity::Odd),
-        3 => Some(Parity::Even),
+        com_port::Parity::None => Some(Parity::None),
+        com_port::Parity::Odd => Some(Parity::Odd),
+        com_port::Parity::Even => Some(Parity::Even),
         _ => None,
     }
 }
 
-pub(crate) const fn stop_bits_to_u8(stop_bits: StopBits) -> u8 {
+pub(crate) const fn stop_bits_to_option(stop_bits: StopBits) -> com_port::StopBits {
     match stop_bits {
-        StopBits::One => 1,
-        StopBits::Two => 2,
+        StopBits::One => com_port::StopBits::One,
+        StopBits::Two => com_port::StopBits::Two,
     }
 }
 
-pub(crate) const fn u8_to_stop_bits(value: u8) -> Option<StopBits> {
+pub(crate) const fn option_to_stop_bits(value: com_port::StopBits) -> Option<StopBits> {
     match value {
-        1 => Some(StopBits::One),
-        2 => Some(StopBits::Two),
+        com_port::StopBits::One => Some(StopBits::One),
+        com_port::StopBits::Two => Some(StopBits::Two),
         _ => None,
     }
 }
 
-pub(crate) const fn flow_control_to_u8(flow_control: FlowControl) -> u8 {
+pub(crate) const fn flow_control_to_option(flow_control: FlowControl) -> com_port::Control {
     match flow_control {
-        FlowControl::None => 1,
-        FlowControl::Software => 2,
-        FlowControl::Hardware => 3,
+        FlowControl::None => com_port::Control::NoFlowControl,
+        FlowControl::Software => com_port::Control::XonXoffFlowControl,
+        FlowControl::Hardware => com_port::Control::HardwareFlowControl,
     }
 }
 
-pub(crate) const fn u8_to_flow_control(value: u8) -> Option<FlowControl> {
+pub(crate) const fn option_to_flow_control(value: com_port::Control) -> Option<FlowControl> {
     match value {
-        1 => Some(FlowControl::None),
-        2 => Some(FlowControl::Software),
-        3 => Some(FlowControl::Hardware),
+        com_port::Control::NoFlowControl => Some(FlowControl::None),
+        com_port::Control::XonXoffFlowControl => Some(FlowControl::Software),
+        com_port::Control::HardwareFlowControl => Some(FlowControl::Hardware),
         _ => None,
     }
 }
@@ -1,313 +1,867 @@
-use crate::serialport_conversions::*;
-use crate::{
-    codes, negotiation, parser, subnegotiation, Command, Negotiation, Parser, Subnegotiation,
-};
-use serialport::{ClearBuffer, FlowControl, SerialPort};
+use crate::access::{self, IpNetwork};
+use crate::backend::{FlowControl, SerialBackend};
+use crate::com_port::{Parity, StopBits};
+use crate::handler::ServerHandler;
+use crate::policy::LinePolicy;
+use crate::selector::{self, DeviceSelector};
+use crate::session::{self, Config, PortSettings, Session, DEFAULT_SIGNATURE, MAX_ANSWER_SIZE};
+use serialport::SerialPort;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use std::io::{self, BufWriter, Read, Write};
-use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+const DEFAULT_BACKLOG: i32 = 128;
+const DEFAULT_BUFFER_SIZE: usize = 256;
+const DEFAULT_REOPEN_DELAY: Duration = Duration::from_millis(100);
+const DEFAULT_REOPEN_MAX_DELAY: Duration = Duration::from_secs(5);
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// How often the client is checked on while waiting to reopen a lost serial port
+const LOST_PORT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Opens the serial port again after it was lost
+type Opener<B> = Box<dyn FnMut() -> Result<Box<B>, Error> + Send>;
 
 #[derive(Debug)]
 pub enum Error {
-    Parsing(parser::Error),
     SerialInit(serialport::Error),
+    // The serial port selector is invalid or selects no single port
+    Selector(selector::Error),
     Serial(io::Error),
     Tcp(io::Error),
+    // The serial port does not support the initial settings
+    UnsupportedSettings(PortSettings),
+    // The thread of a `MultiServer` bridge could not be started,
+    // or the blocking task opening the port of an `AsyncServer` failed
+    Thread(io::Error),
 }
 
-pub struct Server {
-    port: Box<dyn SerialPort>,
-    port_writer: BufWriter<Box<dyn SerialPort>>,
-    tcp_conn: TcpStream,
-    tcp_writer: BufWriter<TcpStream>,
-    tcp_answer_buf: [u8; subnegotiation::MAX_SIZE],
-    parser: Parser,
-    signature: Vec<u8>,
-    suspended_flow_control: FlowControl,
-    break_state: bool,
+impl<E> From<session::Error<E>> for Error
+where
+    io::Error: From<E>,
+{
+    fn from(error: session::Error<E>) -> Self {
+        match error {
+            session::Error::Serial(error) => Error::Serial(error.into()),
+            // The buffers used by the server are always large enough
+            session::Error::BufferTooSmall => Error::Tcp(io::ErrorKind::WriteZero.into()),
+        }
+    }
 }
 
-impl Server {
-    pub fn new<A: ToSocketAddrs>(serial_port_name: &str, tcp_addr: A) -> Result<Self, Error> {
-        let port = serialport::new(serial_port_name, 9600)
-            .open()
-            .map_err(Error::SerialInit)?;
-        let port_clone = port.try_clone().map_err(Error::SerialInit)?;
-        let listener = TcpListener::bind(tcp_addr).map_err(Error::Tcp)?;
-        let (connection, _) = listener.accept().map_err(Error::Tcp)?;
-        connection.set_nonblocking(true).map_err(Error::Tcp)?;
-        let cloned_connection = connection.try_clone().map_err(Error::Tcp)?;
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum State {
+    Listening,
+    Connected(SocketAddr),
+}
 
-        Ok(Server {
-            port: port,
-            parser: Parser::new(),
-            port_writer: BufWriter::new(port_clone),
-            tcp_conn: connection,
-            tcp_writer: BufWriter::new(cloned_connection),
-            tcp_answer_buf: [0; subnegotiation::MAX_SIZE],
-            signature: Vec::new(),
-            suspended_flow_control: FlowControl::None,
-            break_state: false,
-        })
+// Options applied to every accepted client connection
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct SocketOptions {
+    pub(crate) nodelay: bool,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) recv_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    pub(crate) fn apply(&self, socket: SockRef<'_>) -> io::Result<()> {
+        socket.set_tcp_nodelay(self.nodelay)?;
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
     }
+}
 
-    pub fn run(&mut self) -> Result<(), Error> {
-        // Read and handle the data from the TCP connection
-        let mut tcp_data = [0; 256];
-        match self.tcp_conn.read(&mut tcp_data) {
-            Ok(bytes_read) => {
-                self.process_tcp_data(&tcp_data[..bytes_read])?;
-            }
-            Err(error) => match error.kind() {
-                io::ErrorKind::WouldBlock => {}
-                _ => return Err(Error::Tcp(error)),
+// Initial serial port settings and server options, the defaults match `Server::new`
+#[derive(Debug, Clone)]
+pub struct ServerBuilder<H = ()> {
+    pub(crate) handler: H,
+    pub(crate) config: Config,
+    pub(crate) signature: Option<String>,
+    pub(crate) read_timeout: Duration,
+    pub(crate) write_timeout: Duration,
+    pub(crate) socket_options: SocketOptions,
+    pub(crate) backlog: i32,
+    pub(crate) buffer_size: usize,
+    pub(crate) allowed_clients: Vec<IpNetwork>,
+    pub(crate) reopen: bool,
+    pub(crate) reopen_delay: Duration,
+    pub(crate) reopen_max_delay: Duration,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        ServerBuilder {
+            handler: (),
+            config: Config::new(),
+            signature: None,
+            read_timeout: Duration::ZERO,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            socket_options: SocketOptions {
+                nodelay: true,
+                send_buffer_size: None,
+                recv_buffer_size: None,
             },
+            backlog: DEFAULT_BACKLOG,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            allowed_clients: Vec::new(),
+            reopen: false,
+            reopen_delay: DEFAULT_REOPEN_DELAY,
+            reopen_max_delay: DEFAULT_REOPEN_MAX_DELAY,
         }
+    }
+}
 
-        // Read and handle the data from the serial port
-        let mut port_data = [0; 256];
-        match self.port.read(&mut port_data) {
-            Ok(bytes_read) => {
-                for &byte in &port_data[..bytes_read] {
-                    // Escape all IAC bytes
-                    self.tcp_writer.write_all(&[byte]).map_err(Error::Tcp)?;
-                    if byte == codes::IAC {
-                        self.tcp_writer.write_all(&[byte]).map_err(Error::Tcp)?;
-                    }
-                }
-            }
-            Err(error) => match error.kind() {
-                io::ErrorKind::TimedOut => {}
-                _ => return Err(Error::Serial(error)),
-            },
+impl<H: ServerHandler> ServerBuilder<H> {
+    // Sets the hooks called as the server handles the client's requests
+    pub fn handler<T: ServerHandler>(self, handler: T) -> ServerBuilder<T> {
+        ServerBuilder {
+            handler,
+            config: self.config,
+            signature: self.signature,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            socket_options: self.socket_options,
+            backlog: self.backlog,
+            buffer_size: self.buffer_size,
+            allowed_clients: self.allowed_clients,
+            reopen: self.reopen,
+            reopen_delay: self.reopen_delay,
+            reopen_max_delay: self.reopen_max_delay,
         }
+    }
 
-        // Flush the buffered data to be sent
-        self.port_writer.flush().map_err(Error::Serial)?;
-        self.tcp_writer.flush().map_err(Error::Tcp)?;
+    // Sets all the initial line settings at once
+    pub fn port_settings(mut self, port_settings: PortSettings) -> Self {
+        self.config.port_settings = port_settings;
+        self
+    }
 
-        Ok(())
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.config.port_settings.baud_rate = baud_rate;
+        self
     }
 
-    fn process_tcp_data(&mut self, bytes: &[u8]) -> Result<(), Error> {
-        for &byte in bytes {
-            if let Some(event) = self.parser.process_byte(byte).map_err(Error::Parsing)? {
-                let answer_size = self.process_event(event).map_err(Error::Serial)?;
-                self.tcp_writer
-                    .write_all(&self.tcp_answer_buf[..answer_size])
-                    .map_err(Error::Tcp)?;
+    pub fn data_bits(mut self, data_bits: u8) -> Self {
+        self.config.port_settings.data_bits = data_bits;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.config.port_settings.parity = parity;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.config.port_settings.stop_bits = stop_bits;
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.config.port_settings.flow_control = flow_control;
+        self
+    }
+
+    // How long a read waits for serial data, which bounds the latency of the data
+    // received from the client. Only applies to ports opened by the server.
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    // Signature answered to the client's signature queries, by default the crate name
+    // and version followed by the serial port name, truncated to 255 bytes
+    pub fn signature(mut self, signature: &str) -> Self {
+        self.signature = Some(signature.to_owned());
+        self
+    }
+
+    // How long the break requested with the Telnet BREAK command lasts, 250 ms by default
+    pub fn break_duration(mut self, break_duration: Duration) -> Self {
+        self.config.break_duration = break_duration;
+        self
+    }
+
+    // Limits on the line settings the client may change
+    pub fn policy(mut self, policy: LinePolicy) -> Self {
+        self.config.policy = policy;
+        self
+    }
+
+    // Whether to put the port back into the initial settings when a client disconnects
+    pub fn reset_on_disconnect(mut self, reset_on_disconnect: bool) -> Self {
+        self.config.reset_on_disconnect = reset_on_disconnect;
+        self
+    }
+
+    // Sets TCP_NODELAY on client connections, enabled by default
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.socket_options.nodelay = nodelay;
+        self
+    }
+
+    // Maximum number of clients waiting to be accepted
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    // How much data is read from either side at once
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    // How long a client may keep the server from sending to it before it is disconnected,
+    // for example because it stopped reading its data
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = write_timeout.max(Duration::from_millis(1));
+        self
+    }
+
+    // Sets SO_SNDBUF on client connections
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.socket_options.send_buffer_size = Some(size);
+        self
+    }
+
+    // Sets SO_RCVBUF on client connections
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.socket_options.recv_buffer_size = Some(size);
+        self
+    }
+
+    // Only clients from these networks are served, others are disconnected right away.
+    // Every client is served if none are given.
+    pub fn allow_clients(mut self, networks: impl IntoIterator<Item = IpNetwork>) -> Self {
+        self.allowed_clients.extend(networks);
+        self
+    }
+
+    // Opens the serial port again when it fails, for example once a USB adapter is plugged
+    // back in, instead of returning the error from `Server::run`. The client stays connected
+    // and sees CD drop until the port is back with the line settings it had.
+    pub fn reopen(mut self, reopen: bool) -> Self {
+        self.reopen = reopen;
+        self
+    }
+
+    // How long to wait before trying to reopen the port, doubling after every failed attempt
+    pub fn reopen_delay(mut self, delay: Duration, max_delay: Duration) -> Self {
+        self.reopen_delay = delay;
+        self.reopen_max_delay = max_delay.max(delay);
+        self
+    }
+
+    // The serial port is given by its path or by a `DeviceSelector`, like `usb:303a:1001`,
+    // which is looked up again whenever the port is reopened
+    pub fn build<A: ToSocketAddrs>(
+        self,
+        serial_port_name: &str,
+        tcp_addr: A,
+    ) -> Result<Server<dyn SerialPort, H>, Error> {
+        let selector: DeviceSelector = serial_port_name.parse().map_err(Error::Selector)?;
+        let baud_rate = self.config.port_settings.baud_rate;
+        let read_timeout = self.read_timeout;
+        let open = move || {
+            let name = selector.resolve().map_err(Error::Selector)?;
+            serialport::new(name, baud_rate)
+                .timeout(read_timeout)
+                .open()
+                .map_err(Error::SerialInit)
+        };
+        let port = open()?;
+        let config = self.session_config(Some(serial_port_name));
+        let opener: Option<Opener<dyn SerialPort>> = match self.reopen {
+            true => Some(Box::new(open)),
+            false => None,
+        };
+        self.build_with_port(port, opener, config, tcp_addr)
+    }
+
+    // Opens the backend with the function, which also opens it again if `reopen` is set
+    pub fn build_with_opener<B, F, A>(self, mut open: F, tcp_addr: A) -> Result<Server<B, H>, Error>
+    where
+        B: SerialBackend + 'static,
+        F: FnMut() -> Result<B, B::Error> + Send + 'static,
+        A: ToSocketAddrs,
+        io::Error: From<B::Error>,
+    {
+        let port = open().map_err(|error| Error::Serial(error.into()))?;
+        let config = self.session_config(None);
+        let opener: Option<Opener<B>> = match self.reopen {
+            true => Some(Box::new(move || {
+                open()
+                    .map(Box::new)
+                    .map_err(|error| Error::Serial(error.into()))
+            })),
+            false => None,
+        };
+        self.build_with_port(Box::new(port), opener, config, tcp_addr)
+    }
+
+    pub fn build_with_backend<B: SerialBackend, A: ToSocketAddrs>(
+        self,
+        backend: B,
+        tcp_addr: A,
+    ) -> Result<Server<B, H>, Error>
+    where
+        io::Error: From<B::Error>,
+    {
+        let config = self.session_config(None);
+        self.build_with_port(Box::new(backend), None, config, tcp_addr)
+    }
+
+    // The session config with the signature for the given serial port
+    pub(crate) fn session_config(&self, serial_port_name: Option<&str>) -> Config {
+        let mut config = self.config;
+        match (&self.signature, serial_port_name) {
+            (Some(signature), _) => config.set_signature(signature.as_bytes()),
+            (None, Some(name)) => {
+                config.set_signature(format!("{DEFAULT_SIGNATURE} {name}").as_bytes())
             }
+            (None, None) => {}
         }
-        Ok(())
+        config
     }
 
-    fn process_event(&mut self, event: parser::Event) -> Result<usize, io::Error> {
-        match event {
-            parser::Event::Data(byte) => {
-                self.port_writer.write_all(&[byte])?;
-                Ok(0)
-            }
-            parser::Event::Command(command) => self.process_command(command),
-            parser::Event::Negotiation(negotiation) => self.process_negotiation(negotiation),
-            parser::Event::Subnegotiation(subnegotiation) => {
-                self.process_subnegotiation(subnegotiation)
+    fn build_with_port<B: SerialBackend + ?Sized, A: ToSocketAddrs>(
+        self,
+        mut port: Box<B>,
+        opener: Option<Opener<B>>,
+        config: Config,
+        tcp_addr: A,
+    ) -> Result<Server<B, H>, Error>
+    where
+        io::Error: From<B::Error>,
+    {
+        let port_settings = config.port_settings;
+        let supported = port_settings
+            .apply(port.as_mut())
+            .map_err(|error| Error::Serial(error.into()))?;
+        if !supported {
+            return Err(Error::UnsupportedSettings(port_settings));
+        }
+        let listener = bind(tcp_addr, self.backlog).map_err(Error::Tcp)?;
+
+        Ok(Server {
+            port: Some(port),
+            listener,
+            connection: None,
+            session: Session::with_handler(config, self.handler),
+            socket_options: self.socket_options,
+            write_timeout: self.write_timeout,
+            buffers: Buffers::new(self.buffer_size),
+            break_deadline: None,
+            allowed_clients: self.allowed_clients,
+            reopen: opener.map(|open| Reopen {
+                open,
+                delay: self.reopen_delay,
+                max_delay: self.reopen_max_delay,
+                lost: None,
+            }),
+        })
+    }
+}
+
+// Server bridging a TCP client to a serial backend, by default a `serialport` port
+pub struct Server<B: SerialBackend + ?Sized = dyn SerialPort, H = ()> {
+    // None while it is lost
+    port: Option<Box<B>>,
+    listener: TcpListener,
+    connection: Option<Connection>,
+    session: Session<H>,
+    socket_options: SocketOptions,
+    write_timeout: Duration,
+    buffers: Buffers,
+    // When the timed break in progress ends
+    break_deadline: Option<Instant>,
+    allowed_clients: Vec<IpNetwork>,
+    reopen: Option<Reopen<B>>,
+}
+
+struct Reopen<B: ?Sized> {
+    open: Opener<B>,
+    delay: Duration,
+    max_delay: Duration,
+    // While the port is lost, when to try opening it next and the delay used for that
+    lost: Option<(Instant, Duration)>,
+}
+
+struct Connection {
+    tcp_conn: TcpStream,
+    tcp_writer: BufWriter<ClientWriter>,
+    peer_addr: SocketAddr,
+}
+
+// Writes to the non-blocking client connection, waiting up to the socket's write timeout
+// when the client does not take the data right away. A client that takes none of it
+// within that time gets a `TimedOut` error.
+struct ClientWriter(TcpStream);
+
+impl Write for ClientWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.write(buf) {
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                self.0.set_nonblocking(false)?;
+                let result = self.0.write(buf);
+                self.0.set_nonblocking(true)?;
+                // The timeout is reported as WouldBlock on unix
+                result.map_err(|error| match error.kind() {
+                    io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+                    _ => error,
+                })
             }
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+pub(crate) struct Buffers {
+    pub(crate) tcp_data: Vec<u8>,
+    pub(crate) port_data: Vec<u8>,
+    pub(crate) port_out: Vec<u8>,
+    pub(crate) tcp_out: Vec<u8>,
+}
+
+impl Buffers {
+    // Every byte from the client results in at most one byte for the serial port,
+    // while the answers need room for the largest subnegotiation
+    pub(crate) fn new(size: usize) -> Self {
+        Buffers {
+            tcp_data: vec![0; size],
+            port_data: vec![0; size],
+            port_out: vec![0; size],
+            tcp_out: vec![0; size.max(MAX_ANSWER_SIZE)],
         }
     }
+}
+
+impl Server {
+    pub fn new<A: ToSocketAddrs>(serial_port_name: &str, tcp_addr: A) -> Result<Self, Error> {
+        ServerBuilder::new().build(serial_port_name, tcp_addr)
+    }
+
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+}
+
+impl<B: SerialBackend> Server<B>
+where
+    io::Error: From<B::Error>,
+{
+    pub fn with_backend<A: ToSocketAddrs>(backend: B, tcp_addr: A) -> Result<Self, Error> {
+        ServerBuilder::new().build_with_backend(backend, tcp_addr)
+    }
+}
+
+impl<B: SerialBackend + ?Sized, H: ServerHandler> Server<B, H>
+where
+    io::Error: From<B::Error>,
+{
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listener.local_addr().map_err(Error::Tcp)
+    }
 
-    fn process_command(&mut self, command: Command) -> Result<usize, io::Error> {
-        match command {
-            _ => Ok(0),
+    pub fn state(&self) -> State {
+        match &self.connection {
+            Some(connection) => State::Connected(connection.peer_addr),
+            None => State::Listening,
         }
     }
 
-    fn process_negotiation(&mut self, negotiation: Negotiation) -> Result<usize, io::Error> {
-        match negotiation.get_answer() {
-            Some(answer) => {
-                answer.serialize(&mut self.tcp_answer_buf[..negotiation::SIZE]);
-                Ok(negotiation::SIZE)
+    // The signature the connected client identified itself with, if it sent one
+    pub fn client_signature(&self) -> Option<&[u8]> {
+        self.session.client_signature()
+    }
+
+    pub fn handler(&self) -> &H {
+        self.session.handler()
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        self.session.handler_mut()
+    }
+
+    // Whether the serial port failed and is waiting to be reopened
+    pub fn is_port_lost(&self) -> bool {
+        self.reopen
+            .as_ref()
+            .is_some_and(|reopen| reopen.lost.is_some())
+    }
+
+    // Waits for a client while listening, otherwise bridges the data between the client
+    // and the serial port, going back to listening when the client disconnects.
+    // While a lost serial port is reopened, each call checks whether the client left
+    // and waits a little for the next attempt.
+    pub fn run(&mut self) -> Result<(), Error> {
+        if self.is_port_lost() {
+            self.watch_client()?;
+            return self.reopen_port();
+        }
+        match self.serve() {
+            Err(Error::Serial(error)) if self.reopen.is_some() && is_port_loss(&error) => {
+                warn!("lost the serial port, reopening it: {error:?}");
+                self.lose_port()
             }
-            None => Ok(0),
+            Err(error) => {
+                error!("{error:?}");
+                Err(error)
+            }
+            result => result,
         }
     }
 
-    fn process_subnegotiation(
-        &mut self,
-        subnegotiation: Subnegotiation,
-    ) -> Result<usize, io::Error> {
-        let answer_opt = match subnegotiation {
-            Subnegotiation::SetSignature { data, size } => {
-                // An empty signature constitutes a signature query
-                if size == 0 {
-                    let mut data = [0; subnegotiation::MAX_DATA_SIZE];
-                    let size = self.signature.len() as u8;
-                    data.copy_from_slice(&self.signature);
-                    Some(Subnegotiation::SetSignature { data, size })
-                } else {
-                    self.signature.copy_from_slice(&data[..size as usize]);
-                    Some(subnegotiation)
-                }
+    fn serve(&mut self) -> Result<(), Error> {
+        if self.connection.is_none() {
+            // Nothing else can happen while waiting for a client, so a break in progress
+            // is seen through before
+            if let Some(deadline) = self.break_deadline {
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                self.end_timed_break()?;
             }
+            return self.accept();
+        }
 
-            Subnegotiation::SetBaudRate(val) => {
-                if val == 0 {
-                    Some(Subnegotiation::SetBaudRate(self.port.baud_rate()?))
-                } else {
-                    self.port.set_baud_rate(val)?;
-                    Some(subnegotiation)
+        let result = self.transfer().and_then(|()| {
+            self.start_break_timer();
+            match self
+                .break_deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                true => self.end_timed_break(),
+                false => Ok(()),
+            }
+        });
+        // The connection cannot be relied on after any error, so the client is dropped
+        // and the server goes on with the next one
+        match result {
+            Err(Error::Tcp(error)) => {
+                if !is_disconnect(&error) {
+                    warn!("dropping the client after a connection error: {error:?}");
                 }
+                self.disconnect()
             }
+            result => result,
+        }
+    }
 
-            Subnegotiation::SetDataSize(val) => match u8_to_data_bits(val) {
-                Some(data_bits) => {
-                    self.port.set_data_bits(data_bits)?;
-                    Some(subnegotiation)
-                }
-                None => Some(Subnegotiation::SetDataSize(data_bits_to_u8(
-                    self.port.data_bits()?,
-                ))),
-            },
+    // Starts a serial break that `run` ends once the duration has passed
+    pub fn send_break(&mut self, duration: Duration) -> Result<(), Error> {
+        let port = self.port.as_deref_mut().ok_or_else(port_closed)?;
+        self.session
+            .send_break(port, duration)
+            .map_err(|error| Error::Serial(error.into()))?;
+        self.start_break_timer();
+        Ok(())
+    }
 
-            Subnegotiation::SetParity(val) => match u8_to_parity(val) {
-                Some(parity) => {
-                    self.port.set_parity(parity)?;
-                    Some(subnegotiation)
-                }
-                None => Some(Subnegotiation::SetParity(parity_to_u8(self.port.parity()?))),
-            },
+    fn start_break_timer(&mut self) {
+        if let Some(duration) = self.session.take_break_timer() {
+            self.break_deadline = Some(Instant::now() + duration);
+        }
+    }
 
-            Subnegotiation::SetStopSize(val) => match u8_to_stop_bits(val) {
-                Some(stop_bits) => {
-                    self.port.set_stop_bits(stop_bits)?;
-                    Some(subnegotiation)
-                }
-                None => Some(Subnegotiation::SetStopSize(stop_bits_to_u8(
-                    self.port.stop_bits()?,
-                ))),
-            },
+    fn end_timed_break(&mut self) -> Result<(), Error> {
+        self.break_deadline = None;
+        let port = self.port.as_deref_mut().ok_or_else(port_closed)?;
+        self.session
+            .end_timed_break(port)
+            .map_err(|error| Error::Serial(error.into()))
+    }
 
-            Subnegotiation::SetControl(val) => self.handle_set_control(val)?,
+    // Tells the client the port is gone, the lines stay down until it is reopened.
+    // The port is closed right away since serial ports are usually opened exclusively,
+    // the device would not open again while it is still held.
+    fn lose_port(&mut self) -> Result<(), Error> {
+        self.port = None;
+        self.break_deadline = None;
+        if let Some(connection) = &mut self.connection {
+            let tcp_out = &mut self.buffers.tcp_out;
+            let size = self.session.port_lost::<io::Error>(tcp_out)?;
+            // A client that went away meanwhile is noticed once the port is back
+            let _ = connection
+                .tcp_writer
+                .write_all(&tcp_out[..size])
+                .and_then(|()| connection.tcp_writer.flush());
+        }
+        if let Some(reopen) = &mut self.reopen {
+            reopen.lost = Some((Instant::now() + reopen.delay, reopen.delay));
+        }
+        Ok(())
+    }
 
-            Subnegotiation::FlowControlSuspend => {
-                self.suspended_flow_control = self.port.flow_control()?;
-                self.port.set_flow_control(FlowControl::None)?;
-                Some(subnegotiation)
-            }
+    fn reopen_port(&mut self) -> Result<(), Error> {
+        let Some(reopen) = &mut self.reopen else {
+            return Ok(());
+        };
+        let Some((retry_at, delay)) = reopen.lost else {
+            return Ok(());
+        };
+        let remaining = retry_at.saturating_duration_since(Instant::now());
+        if !remaining.is_zero() {
+            std::thread::sleep(remaining.min(LOST_PORT_POLL_INTERVAL));
+            return Ok(());
+        }
 
-            Subnegotiation::FlowControlResume => {
-                self.port.set_flow_control(self.suspended_flow_control)?;
-                Some(subnegotiation)
+        let restored = (reopen.open)().and_then(|mut port| {
+            self.session
+                .restore(port.as_mut())
+                .map_err(|error| Error::Serial(error.into()))?;
+            Ok(port)
+        });
+        match restored {
+            Ok(port) => {
+                info!("reopened the serial port");
+                self.port = Some(port);
+                reopen.lost = None;
             }
+            Err(error) => {
+                let delay = delay.saturating_mul(2).min(reopen.max_delay);
+                debug!("cannot reopen the serial port yet, retrying in {delay:?}: {error:?}");
+                reopen.lost = Some((Instant::now() + delay, delay));
+            }
+        }
+        Ok(())
+    }
 
-            Subnegotiation::PurgeData(val) => self.handle_purge_data(val)?,
-
-            _ => None,
+    // Notices the client leaving while the port is lost. What it sends in the meantime
+    // has no port to go to and is dropped.
+    fn watch_client(&mut self) -> Result<(), Error> {
+        let Some(connection) = &mut self.connection else {
+            return Ok(());
         };
-
-        match answer_opt {
-            Some(answer) => Ok(answer.serialize_server(&mut self.tcp_answer_buf)),
-            None => Ok(0),
+        match connection.tcp_conn.read(&mut self.buffers.tcp_data) {
+            Ok(0) => self.disconnect(),
+            Ok(size) => {
+                debug!("dropped {size} bytes from the client while the serial port is lost");
+                Ok(())
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(error) if is_disconnect(&error) => self.disconnect(),
+            Err(error) => Err(Error::Tcp(error)),
         }
     }
 
-    fn handle_set_control(&mut self, val: u8) -> Result<Option<Subnegotiation>, io::Error> {
-        match val {
-            0 => Ok(Some(Subnegotiation::SetControl(flow_control_to_u8(
-                self.port.flow_control()?,
-            )))),
-            1 | 2 | 3 => {
-                self.port
-                    .set_flow_control(u8_to_flow_control(val).unwrap())?;
-                Ok(Some(Subnegotiation::SetControl(val)))
-            }
-            4 => match self.break_state {
-                true => Ok(Some(Subnegotiation::SetControl(5))),
-                false => Ok(Some(Subnegotiation::SetControl(6))),
-            },
-            5 => {
-                self.port.set_break()?;
-                self.break_state = true;
-                Ok(Some(Subnegotiation::SetControl(val)))
-            }
-            6 => {
-                self.port.clear_break()?;
-                self.break_state = false;
-                Ok(Some(Subnegotiation::SetControl(val)))
+    fn accept(&mut self) -> Result<(), Error> {
+        let (tcp_conn, peer_addr) = match self.listener.accept() {
+            // A client that left before it was accepted is no reason to stop listening
+            Err(error) if is_disconnect(&error) => return Ok(()),
+            result => result.map_err(Error::Tcp)?,
+        };
+        if !access::is_allowed(&self.allowed_clients, peer_addr.ip()) {
+            warn!("refused client {peer_addr}, it is not among the allowed clients");
+            return Ok(());
+        }
+        let cloned_tcp_conn = match self.prepare(&tcp_conn) {
+            Ok(cloned_tcp_conn) => cloned_tcp_conn,
+            Err(error) => {
+                warn!("dropping client {peer_addr}, its connection cannot be set up: {error:?}");
+                return Ok(());
             }
-            7 => match self.port.read_data_set_ready()? {
-                true => Ok(Some(Subnegotiation::SetControl(8))),
-                false => Ok(Some(Subnegotiation::SetControl(9))),
-            },
-            8 => {
-                self.port.write_data_terminal_ready(true)?;
-                Ok(Some(Subnegotiation::SetControl(val)))
+        };
+
+        self.connection = Some(Connection {
+            tcp_conn,
+            tcp_writer: BufWriter::new(ClientWriter(cloned_tcp_conn)),
+            peer_addr,
+        });
+        self.session.connect();
+        info!("client {peer_addr} connected");
+        Ok(())
+    }
+
+    // Applies the options to a new client connection and clones it for writing
+    fn prepare(&self, tcp_conn: &TcpStream) -> io::Result<TcpStream> {
+        self.socket_options.apply(SockRef::from(tcp_conn))?;
+        tcp_conn.set_write_timeout(Some(self.write_timeout))?;
+        tcp_conn.set_nonblocking(true)?;
+        tcp_conn.try_clone()
+    }
+
+    fn disconnect(&mut self) -> Result<(), Error> {
+        if let Some(connection) = self.connection.take() {
+            info!("client {} disconnected", connection.peer_addr);
+        }
+        self.break_deadline = None;
+        match self.port.as_deref_mut() {
+            Some(port) => self
+                .session
+                .disconnect(port)
+                .map_err(|error| Error::Serial(error.into())),
+            None => {
+                self.session.disconnect_lost_port();
+                Ok(())
             }
-            9 => {
-                self.port.write_data_terminal_ready(false)?;
-                Ok(Some(Subnegotiation::SetControl(val)))
+        }
+    }
+
+    fn transfer(&mut self) -> Result<(), Error> {
+        let Server {
+            port,
+            connection,
+            session,
+            buffers,
+            ..
+        } = self;
+        let (Some(connection), Some(port)) = (connection.as_mut(), port.as_deref_mut()) else {
+            return Ok(());
+        };
+        let Buffers {
+            tcp_data,
+            port_data,
+            port_out,
+            tcp_out,
+        } = buffers;
+
+        // Read and handle the data from the TCP connection
+        match connection.tcp_conn.read(tcp_data) {
+            Ok(0) => return Err(Error::Tcp(io::ErrorKind::UnexpectedEof.into())),
+            Ok(bytes_read) => {
+                let mut input = &tcp_data[..bytes_read];
+                while !input.is_empty() {
+                    let progress = session.process_network_data(port, input, port_out, tcp_out)?;
+                    write_all(port, &port_out[..progress.serial_produced])
+                        .map_err(Error::Serial)?;
+                    connection
+                        .tcp_writer
+                        .write_all(&tcp_out[..progress.network_produced])
+                        .map_err(Error::Tcp)?;
+                    input = &input[progress.consumed..];
+                }
             }
-            10 => match self.port.read_clear_to_send()? {
-                true => Ok(Some(Subnegotiation::SetControl(11))),
-                false => Ok(Some(Subnegotiation::SetControl(12))),
+            Err(error) => match error.kind() {
+                io::ErrorKind::WouldBlock => {}
+                _ => return Err(Error::Tcp(error)),
             },
-            11 => {
-                self.port.write_request_to_send(true)?;
-                Ok(Some(Subnegotiation::SetControl(val)))
-            }
-            12 => {
-                self.port.write_request_to_send(false)?;
-                Ok(Some(Subnegotiation::SetControl(val)))
+        }
+
+        // Read and handle the data from the serial port, unless the client suspended it
+        if !session.is_output_suspended() {
+            let bytes_read = port
+                .read(port_data)
+                .map_err(|error| Error::Serial(error.into()))?;
+            let mut input = &port_data[..bytes_read];
+            while !input.is_empty() {
+                let progress = session.process_serial_data(input, tcp_out);
+                connection
+                    .tcp_writer
+                    .write_all(&tcp_out[..progress.network_produced])
+                    .map_err(Error::Tcp)?;
+                input = &input[progress.consumed..];
             }
-            _ => Ok(None),
         }
+
+        // Notify the client about the line and modem state changes
+        let size = session.process_port_state(port, tcp_out)?;
+        connection
+            .tcp_writer
+            .write_all(&tcp_out[..size])
+            .map_err(Error::Tcp)?;
+
+        // Flush the buffered data to be sent
+        connection.tcp_writer.flush().map_err(Error::Tcp)?;
+
+        Ok(())
     }
+}
 
-    fn handle_purge_data(&mut self, val: u8) -> Result<Option<Subnegotiation>, io::Error> {
-        match val {
-            1 => {
-                self.port.clear(ClearBuffer::Input)?;
-                Ok(Some(Subnegotiation::PurgeData(val)))
-            }
-            2 => {
-                self.port.clear(ClearBuffer::Output)?;
-                Ok(Some(Subnegotiation::PurgeData(val)))
-            }
-            3 => {
-                self.port.clear(ClearBuffer::Input)?;
-                self.port.clear(ClearBuffer::Output)?;
-                Ok(Some(Subnegotiation::PurgeData(val)))
-            }
-            _ => Ok(None),
+fn port_closed() -> Error {
+    Error::Serial(io::ErrorKind::NotConnected.into())
+}
+
+// Binds to the first of the addresses that works, with the given listen backlog
+pub(crate) fn bind<A: ToSocketAddrs>(tcp_addr: A, backlog: i32) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for addr in tcp_addr.to_socket_addrs()? {
+        match bind_addr(addr, backlog) {
+            Ok(listener) => return Ok(listener),
+            Err(error) => last_error = Some(error),
         }
     }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Could not resolve to any addresses",
+        )
+    }))
 }
 
-impl Negotiation {
-    fn get_answer(&self) -> Option<Negotiation> {
-        match (self.intent, self.option) {
-            (
-                negotiation::Intent::Will,
-                negotiation::Option::Binary
-                | negotiation::Option::ComPort
-                | negotiation::Option::SuppressGoAhead,
-            ) => Some(Negotiation {
-                intent: negotiation::Intent::Do,
-                option: self.option,
-            }),
-            (
-                negotiation::Intent::Do,
-                negotiation::Option::Binary
-                | negotiation::Option::ComPort
-                | negotiation::Option::SuppressGoAhead,
-            ) => None,
-            (negotiation::Intent::Will, _) => Some(Negotiation {
-                intent: negotiation::Intent::Dont,
-                option: self.option,
-            }),
-            (negotiation::Intent::Do, _) => Some(Negotiation {
-                intent: negotiation::Intent::Wont,
-                option: self.option,
-            }),
-            _ => panic!(),
+fn bind_addr(addr: SocketAddr, backlog: i32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // Like the standard library, so a restarted server can bind while old connections linger
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    Ok(socket.into())
+}
+
+// Errors signaling that the client has gone away, or stopped taking its data
+pub(crate) fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+// Whether the serial port error means the device is gone, like an unplugged USB adapter,
+// rather than a rejected request or a timeout the port recovers from
+pub(crate) fn is_port_loss(error: &io::Error) -> bool {
+    // ENXIO, ENODEV and EIO, which serialport only reports by their description
+    const LOSS_ERRNOS: [(i32, &str); 3] = [
+        (6, "No such device or address"),
+        (19, "No such device"),
+        (5, "I/O error"),
+    ];
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::NotFound => true,
+        io::ErrorKind::Other if error.raw_os_error().is_none() => {
+            let description = error.to_string();
+            LOSS_ERRNOS.iter().any(|(_, text)| description == *text)
+        }
+        _ => {
+            cfg!(unix)
+                && error
+                    .raw_os_error()
+                    .is_some_and(|errno| LOSS_ERRNOS.iter().any(|(code, _)| errno == *code))
+        }
+    }
+}
+
+// Writes all the data to the serial backend without waiting for it to be transmitted,
+// the data left in the output buffer is what FLOWCONTROL-SUSPEND is based on
+fn write_all<B: SerialBackend + ?Sized>(port: &mut B, mut data: &[u8]) -> Result<(), io::Error>
+where
+    io::Error: From<B::Error>,
+{
+    while !data.is_empty() {
+        match port.write(data)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            bytes_written => data = &data[bytes_written..],
         }
     }
+    Ok(())
 }
@@ -0,0 +1,839 @@
+use crate::backend::{FlowControl, SerialBackend};
+use crate::com_port::{Control, LineState, ModemState, Parity, Purge, StopBits};
+use crate::handler::{Direction, ServerHandler};
+use crate::negotiation::{self, OptionTable, Side};
+use crate::policy::LinePolicy;
+use crate::{encoder, parser, subnegotiation, Command, Negotiation, Parser, Subnegotiation};
+use core::time::Duration;
+
+// Free space the network output needs for the answer to a single client packet,
+// a buffer of this size also fits the notifications
+pub const MAX_ANSWER_SIZE: usize = subnegotiation::MAX_SIZE;
+// Free space the network output needs for the line and modem state notifications,
+// two subnegotiations with a single, possibly escaped, data byte, followed by a
+// flow control subnegotiation without data
+pub const MAX_NOTIFICATION_SIZE: usize =
+    2 * (subnegotiation::NONDATA_SIZE + 2) + subnegotiation::NONDATA_SIZE;
+// Bytes waiting in the serial output buffer above which the client is asked to
+// suspend sending data, and below which it is asked to resume
+pub const DEFAULT_SUSPEND_THRESHOLD: u32 = 2048;
+pub const DEFAULT_RESUME_THRESHOLD: u32 = 512;
+// How long the break requested with the Telnet BREAK command lasts
+pub const DEFAULT_BREAK_DURATION: Duration = Duration::from_millis(250);
+// Signature answered to the client's signature queries unless configured otherwise
+pub const DEFAULT_SIGNATURE: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error<E> {
+    Serial(E),
+    BufferTooSmall,
+}
+
+// How much of the input was processed and how much output was written to each side
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Progress {
+    pub consumed: usize,
+    pub serial_produced: usize,
+    pub network_produced: usize,
+}
+
+// Serial line settings a port is opened with, by default 9600 baud 8N1 without flow control
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PortSettings {
+    pub baud_rate: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl Default for PortSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl PortSettings {
+    pub const DEFAULT: Self = PortSettings {
+        baud_rate: 9600,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        flow_control: FlowControl::None,
+    };
+
+    // Applies the settings and clears the break state,
+    // returning false if the port does not support some of them
+    pub fn apply<B: SerialBackend + ?Sized>(&self, port: &mut B) -> Result<bool, B::Error> {
+        debug!("applying {self:?}");
+        let mut supported = port.set_baud_rate(self.baud_rate)?;
+        supported &= port.set_data_bits(self.data_bits)?;
+        supported &= port.set_parity(self.parity)?;
+        supported &= port.set_stop_bits(self.stop_bits)?;
+        supported &= port.set_flow_control(self.flow_control)?;
+        port.set_break(false)?;
+        if !supported {
+            warn!("the serial port does not support {self:?}");
+        }
+        Ok(supported)
+    }
+}
+
+// Server options kept by a `Session` across client connections
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Config {
+    // Settings the port is put back into when a client disconnects
+    pub port_settings: PortSettings,
+    // Limits on the settings the client may change
+    pub policy: LinePolicy,
+    // Whether to put the port back into `port_settings` when a client disconnects,
+    // otherwise the next client inherits the settings of the previous one
+    pub reset_on_disconnect: bool,
+    pub signature: [u8; subnegotiation::MAX_DATA_SIZE],
+    pub signature_size: u8,
+    pub suspend_threshold: u32,
+    pub resume_threshold: u32,
+    // How long the break requested with the Telnet BREAK command lasts
+    pub break_duration: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    pub const fn new() -> Self {
+        let mut config = Config {
+            port_settings: PortSettings::DEFAULT,
+            policy: LinePolicy::ANY,
+            reset_on_disconnect: true,
+            signature: [0; subnegotiation::MAX_DATA_SIZE],
+            signature_size: 0,
+            suspend_threshold: DEFAULT_SUSPEND_THRESHOLD,
+            resume_threshold: DEFAULT_RESUME_THRESHOLD,
+            break_duration: DEFAULT_BREAK_DURATION,
+        };
+        config.set_signature(DEFAULT_SIGNATURE.as_bytes());
+        config
+    }
+
+    // Sets the signature answered to signature queries,
+    // truncated to the longest one a subnegotiation can carry
+    pub const fn set_signature(&mut self, signature: &[u8]) {
+        let size = if signature.len() < u8::MAX as usize {
+            signature.len()
+        } else {
+            u8::MAX as usize
+        };
+        self.signature = [0; subnegotiation::MAX_DATA_SIZE];
+        let mut i = 0;
+        while i < size {
+            self.signature[i] = signature[i];
+            i += 1;
+        }
+        self.signature_size = size as u8;
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.signature[..self.signature_size as usize]
+    }
+}
+
+// Protocol state of the server side of a client connection, independent of
+// how the serial port and the network connection are read and written.
+// The serial port settings are applied through the `SerialBackend` while the data
+// is exchanged through caller provided buffers, so no allocation is needed.
+// The handler is told about the client's requests and may change or refuse them.
+pub struct Session<H = ()> {
+    config: Config,
+    handler: H,
+    client: ClientState,
+    // The serial port API cannot read back the output lines, so their state is tracked.
+    // Opening a port asserts both on most platforms.
+    dtr_state: bool,
+    rts_state: bool,
+    // The line settings applied so far, to put a reopened port back into
+    line_settings: PortSettings,
+}
+
+// Everything that is forgotten when the client disconnects
+struct ClientState {
+    parser: Parser,
+    // Signature the client sent about itself, if any
+    client_signature: [u8; subnegotiation::MAX_DATA_SIZE],
+    client_signature_size: u8,
+    // The client asked to stop receiving serial data
+    output_suspended: bool,
+    // The client was asked to stop sending data because the serial output is backlogged
+    input_suspended: bool,
+    break_state: bool,
+    // The break is timed and ended by the driver, unless the client changes it meanwhile
+    timed_break: bool,
+    // Duration of the timed break just started, until the driver takes it
+    break_timer: Option<Duration>,
+    linestate_mask: LineState,
+    modemstate_mask: ModemState,
+    line_state: LineState,
+    modem_state: ModemState,
+    notify_modem_state: bool,
+    options: OptionTable,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientState {
+    const fn new() -> Self {
+        ClientState {
+            parser: Parser::new(),
+            client_signature: [0; subnegotiation::MAX_DATA_SIZE],
+            client_signature_size: 0,
+            output_suspended: false,
+            input_suspended: false,
+            break_state: false,
+            timed_break: false,
+            break_timer: None,
+            linestate_mask: LineState::empty(),
+            modemstate_mask: ModemState::all(),
+            line_state: LineState::empty(),
+            modem_state: ModemState::empty(),
+            notify_modem_state: false,
+            options: supported_options(),
+        }
+    }
+}
+
+// The Telnet options a client may enable on either side of the connection
+const fn supported_options() -> OptionTable {
+    let mut options = OptionTable::new();
+    options.support(Side::Local, negotiation::Option::Binary);
+    options.support(Side::Local, negotiation::Option::SuppressGoAhead);
+    options.support(Side::Local, negotiation::Option::ComPort);
+    options.support(Side::Remote, negotiation::Option::Binary);
+    options.support(Side::Remote, negotiation::Option::SuppressGoAhead);
+    options.support(Side::Remote, negotiation::Option::ComPort);
+    options
+}
+
+// Reads the line state bits that can be detected through the serial port API and the modem state bits
+fn read_port_state<B: SerialBackend + ?Sized>(
+    port: &mut B,
+) -> Result<(LineState, ModemState), B::Error> {
+    let mut line_state = LineState::empty();
+    if port.bytes_to_read()? > 0 {
+        line_state |= LineState::DATA_READY;
+    }
+    if port.bytes_to_write()? == 0 {
+        line_state |=
+            LineState::TRANSFER_HOLDING_REGISTER_EMPTY | LineState::TRANSFER_SHIFT_REGISTER_EMPTY;
+    }
+
+    let mut modem_state = ModemState::empty();
+    if port.read_clear_to_send()? {
+        modem_state |= ModemState::CTS;
+    }
+    if port.read_data_set_ready()? {
+        modem_state |= ModemState::DSR;
+    }
+    if port.read_ring_indicator()? {
+        modem_state |= ModemState::RI;
+    }
+    if port.read_carrier_detect()? {
+        modem_state |= ModemState::CD;
+    }
+
+    Ok((line_state, modem_state))
+}
+
+impl Session {
+    pub const fn new() -> Self {
+        Self::with_config(Config::new())
+    }
+
+    pub const fn with_config(config: Config) -> Self {
+        Self::with_handler(config, ())
+    }
+}
+
+impl<H: ServerHandler> Session<H> {
+    pub const fn with_handler(config: Config, handler: H) -> Self {
+        Session {
+            config,
+            handler,
+            client: ClientState::new(),
+            dtr_state: true,
+            rts_state: true,
+            line_settings: config.port_settings,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // The line settings in effect, the initial ones with the client's changes applied
+    pub fn line_settings(&self) -> PortSettings {
+        self.line_settings
+    }
+
+    // Puts a reopened serial port back into the state the client left the previous one in,
+    // returning false if the port does not support some of the line settings
+    pub fn restore<B: SerialBackend + ?Sized>(&mut self, port: &mut B) -> Result<bool, B::Error> {
+        let supported = self.line_settings.apply(port)?;
+        port.write_data_terminal_ready(self.dtr_state)?;
+        port.write_request_to_send(self.rts_state)?;
+        self.client.break_state = false;
+        self.client.timed_break = false;
+        Ok(supported)
+    }
+
+    // Lets the client know the serial port is gone by reporting all modem lines down,
+    // which drops CD. `process_port_state` reports them again once the port is back.
+    // `network_out` has to hold at least `MAX_NOTIFICATION_SIZE` bytes.
+    pub fn port_lost<E>(&mut self, network_out: &mut [u8]) -> Result<usize, Error<E>> {
+        if network_out.len() < MAX_NOTIFICATION_SIZE {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(self.notify_modem_state(ModemState::empty(), network_out))
+    }
+
+    // The signature the client identified itself with, until it disconnects
+    pub fn client_signature(&self) -> Option<&[u8]> {
+        match self.client.client_signature_size {
+            0 => None,
+            size => Some(&self.client.client_signature[..size as usize]),
+        }
+    }
+
+    // Sets how many bytes may wait in the serial output buffer before the client is asked
+    // to suspend sending data, and how few before it is asked to resume
+    pub fn set_backlog_thresholds(&mut self, suspend: u32, resume: u32) {
+        self.config.suspend_threshold = suspend;
+        self.config.resume_threshold = resume.min(suspend);
+    }
+
+    // Whether the client asked to stop receiving serial data, the serial port should
+    // not be read in the meantime so the data stays in its buffer
+    pub fn is_output_suspended(&self) -> bool {
+        self.client.output_suspended
+    }
+
+    // Whether the client was asked to stop sending data, the port state has to be
+    // processed regularly in the meantime so it is asked to resume once the backlog clears
+    pub fn is_input_suspended(&self) -> bool {
+        self.client.input_suspended
+    }
+
+    // Sets the break state for the given duration without waiting for it to pass,
+    // the driver ends it with `end_timed_break` once `take_break_timer` says so
+    pub fn send_break<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+        duration: Duration,
+    ) -> Result<(), B::Error> {
+        port.set_break(true)?;
+        self.client.break_state = true;
+        self.client.timed_break = true;
+        self.client.break_timer = Some(duration);
+        Ok(())
+    }
+
+    // How long the break started since the last call lasts, drivers call this
+    // after processing the client's data and end the break once the time has passed
+    pub fn take_break_timer(&mut self) -> Option<Duration> {
+        self.client.break_timer.take()
+    }
+
+    // Ends a timed break, unless the client took over the break state in the meantime.
+    // The handler is told the break is cleared, but cannot keep it on.
+    pub fn end_timed_break<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+    ) -> Result<(), B::Error> {
+        if self.client.timed_break {
+            self.handler.on_break(false);
+            port.set_break(false)?;
+            self.client.break_state = false;
+            self.client.timed_break = false;
+        }
+        Ok(())
+    }
+
+    // Lets the handler know a client connected, drivers call this before passing on its data
+    pub fn connect(&mut self) {
+        self.handler.on_connect();
+    }
+
+    // Forgets the state of the previous client and, unless disabled in the config,
+    // puts the port back into the configured settings
+    pub fn disconnect<B: SerialBackend + ?Sized>(&mut self, port: &mut B) -> Result<(), B::Error> {
+        self.end_timed_break(port)?;
+        self.client = ClientState::new();
+        self.handler.on_disconnect();
+        if self.config.reset_on_disconnect {
+            info!("restoring the initial line settings");
+            self.config.port_settings.apply(port)?;
+            self.line_settings = self.config.port_settings;
+        }
+        Ok(())
+    }
+
+    // Forgets the previous client while the serial port is lost, `restore` then puts the
+    // reopened port into the settings the next client starts with
+    pub fn disconnect_lost_port(&mut self) {
+        self.client = ClientState::new();
+        self.handler.on_disconnect();
+        if self.config.reset_on_disconnect {
+            self.line_settings = self.config.port_settings;
+        }
+    }
+
+    // Handles the data received from the client, writing the data meant for the serial port
+    // to `serial_out` and the answers meant for the client to `network_out`.
+    // Processing stops early once either output runs out of space,
+    // `network_out` has to hold at least `MAX_ANSWER_SIZE` bytes.
+    pub fn process_network_data<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+        input: &[u8],
+        serial_out: &mut [u8],
+        network_out: &mut [u8],
+    ) -> Result<Progress, Error<B::Error>> {
+        if serial_out.is_empty() || network_out.len() < MAX_ANSWER_SIZE {
+            return Err(Error::BufferTooSmall);
+        }
+
+        // Every input byte results in at most one data byte for the serial port
+        let input = &input[..input.len().min(serial_out.len())];
+        let mut remaining = input;
+        let mut progress = Progress::default();
+        while network_out.len() - progress.network_produced >= MAX_ANSWER_SIZE {
+            let mut events = self.client.parser.feed(remaining);
+            let next = events.next();
+            remaining = events.remaining();
+            let Some(result) = next else {
+                break;
+            };
+
+            let event = match result {
+                Ok(parser::Event::Data(data)) => {
+                    trace!("{} bytes to the serial port", data.len());
+                    self.handler.on_data(Direction::ToSerial, data);
+                    let serial_produced = progress.serial_produced + data.len();
+                    serial_out[progress.serial_produced..serial_produced].copy_from_slice(data);
+                    progress.serial_produced = serial_produced;
+                    continue;
+                }
+                Ok(event) => event,
+                // Malformed packets are dropped, the parser recovers with the following byte
+                Err(error) => {
+                    warn!("dropped malformed data from the client: {error:?}");
+                    continue;
+                }
+            };
+            let answer_buf = &mut network_out[progress.network_produced..];
+            progress.network_produced += self
+                .process_event(port, event, answer_buf)
+                .map_err(Error::Serial)?;
+        }
+        progress.consumed = input.len() - remaining.len();
+        Ok(progress)
+    }
+
+    // Escapes the data received from the serial port into `network_out`,
+    // stopping early once it runs out of space
+    pub fn process_serial_data(&mut self, input: &[u8], network_out: &mut [u8]) -> Progress {
+        let encoded = encoder::encode_data(input, network_out);
+        trace!("{} bytes to the client", encoded.consumed);
+        self.handler
+            .on_data(Direction::ToNetwork, &input[..encoded.consumed]);
+        Progress {
+            consumed: encoded.consumed,
+            serial_produced: 0,
+            network_produced: encoded.produced,
+        }
+    }
+
+    // Notifies the client about the line and modem state changes it is interested in
+    // and asks it to suspend or resume sending data depending on the serial output backlog,
+    // `network_out` has to hold at least `MAX_NOTIFICATION_SIZE` bytes
+    pub fn process_port_state<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+        network_out: &mut [u8],
+    ) -> Result<usize, Error<B::Error>> {
+        if network_out.len() < MAX_NOTIFICATION_SIZE {
+            return Err(Error::BufferTooSmall);
+        }
+        let (line_state, modem_state) = read_port_state(port).map_err(Error::Serial)?;
+        let mut size = self.notify_modem_state(modem_state, network_out);
+
+        let line_changes = LineState::from_u8(self.client.line_state.to_u8() ^ line_state.to_u8());
+        if line_changes.intersects(self.client.linestate_mask) {
+            let notification =
+                Subnegotiation::NotifyLineState(line_state & self.client.linestate_mask);
+            debug!("sending {notification}");
+            size += notification.serialize_server(&mut network_out[size..]);
+        }
+        self.client.line_state = line_state;
+
+        let backlog = port.bytes_to_write().map_err(Error::Serial)?;
+        if !self.client.input_suspended && backlog > self.config.suspend_threshold {
+            self.client.input_suspended = true;
+            debug!(
+                "sending {} with {backlog} bytes to write",
+                Subnegotiation::FlowControlSuspend
+            );
+            size += Subnegotiation::FlowControlSuspend.serialize_server(&mut network_out[size..]);
+        } else if self.client.input_suspended && backlog <= self.config.resume_threshold {
+            self.client.input_suspended = false;
+            debug!(
+                "sending {} with {backlog} bytes to write",
+                Subnegotiation::FlowControlResume
+            );
+            size += Subnegotiation::FlowControlResume.serialize_server(&mut network_out[size..]);
+        }
+
+        Ok(size)
+    }
+
+    // Notifies the client about the modem lines that changed, if it is interested in them
+    fn notify_modem_state(&mut self, modem_state: ModemState, network_out: &mut [u8]) -> usize {
+        // The lower modem state bits signal which of the upper ones changed
+        let changes = (self.client.modem_state.to_u8() ^ modem_state.to_u8()) >> 4;
+        let mut deltas = ModemState::from_u8(changes)
+            & (ModemState::DELTA_CTS | ModemState::DELTA_DSR | ModemState::DELTA_CD);
+        if self.client.modem_state.contains(ModemState::RI) && !modem_state.contains(ModemState::RI)
+        {
+            deltas |= ModemState::TRAILING_EDGE_RI;
+        }
+        self.client.modem_state = modem_state;
+
+        let masked_modem_state = (modem_state | deltas) & self.client.modemstate_mask;
+        if deltas.intersects(self.client.modemstate_mask) || self.client.notify_modem_state {
+            self.client.notify_modem_state = false;
+            let notification = Subnegotiation::NotifyModemState(masked_modem_state);
+            debug!("sending {notification}");
+            return notification.serialize_server(network_out);
+        }
+        0
+    }
+
+    fn process_event<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+        event: parser::Event<&[u8]>,
+        answer_buf: &mut [u8],
+    ) -> Result<usize, B::Error> {
+        match event {
+            parser::Event::Data(_) => Ok(0),
+            parser::Event::Command(command) => {
+                debug!("received {command:?}");
+                self.process_command(port, command).map(|_| 0)
+            }
+            parser::Event::Negotiation(negotiation) => {
+                debug!("received {:?} {:?}", negotiation.intent, negotiation.option);
+                Ok(self.process_negotiation(negotiation, answer_buf))
+            }
+            parser::Event::Subnegotiation(subnegotiation) => {
+                debug!("received {subnegotiation}");
+                let line_settings = self.line_settings;
+                let answer = self.process_subnegotiation(port, subnegotiation)?;
+                if self.line_settings != line_settings {
+                    info!("line settings changed to {:?}", self.line_settings);
+                }
+                Ok(answer.map_or(0, |answer| {
+                    debug!("sending {answer}");
+                    answer.serialize_server(answer_buf)
+                }))
+            }
+        }
+    }
+
+    fn process_command<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+        command: Command,
+    ) -> Result<(), B::Error> {
+        match command {
+            Command::Break if self.handler.on_break(true) == Some(true) => {
+                self.send_break(port, self.config.break_duration)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn process_negotiation(&mut self, negotiation: Negotiation, answer_buf: &mut [u8]) -> usize {
+        let reply = self.client.options.receive(negotiation);
+        if let Some(change) = reply.change {
+            debug!("option {change:?}");
+        }
+        match reply.answer {
+            Some(answer) => {
+                debug!("sending {:?} {:?}", answer.intent, answer.option);
+                answer.serialize(&mut answer_buf[..negotiation::SIZE]);
+                negotiation::SIZE
+            }
+            None => 0,
+        }
+    }
+
+    fn process_subnegotiation<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+        subnegotiation: Subnegotiation,
+    ) -> Result<Option<Subnegotiation>, B::Error> {
+        let answer_opt = match subnegotiation {
+            Subnegotiation::SetSignature { data, size } => {
+                // An empty signature constitutes a query for the server's signature
+                if size == 0 {
+                    Some(Subnegotiation::SetSignature {
+                        data: self.config.signature,
+                        size: self.config.signature_size,
+                    })
+                } else {
+                    // The client only tells its own signature, there is nothing to answer
+                    self.client.client_signature = data;
+                    self.client.client_signature_size = size;
+                    None
+                }
+            }
+
+            Subnegotiation::SetBaudRate(0) => Some(Subnegotiation::SetBaudRate(port.baud_rate()?)),
+
+            // The policy has the last word on what the handler lets through
+            Subnegotiation::SetBaudRate(val) => match self
+                .handler
+                .on_baud_rate(val)
+                .and_then(|val| self.config.policy.baud_rate.apply(val))
+            {
+                Some(val) if port.set_baud_rate(val)? => {
+                    self.line_settings.baud_rate = val;
+                    Some(Subnegotiation::SetBaudRate(val))
+                }
+                _ => Some(Subnegotiation::SetBaudRate(port.baud_rate()?)),
+            },
+
+            // Refused and unsupported values are answered with the current setting
+            Subnegotiation::SetDataSize(val) => {
+                match self.config.policy.data_bits.permits(&val) && port.set_data_bits(val)? {
+                    true => {
+                        self.line_settings.data_bits = val;
+                        Some(subnegotiation)
+                    }
+                    false => Some(Subnegotiation::SetDataSize(port.data_bits()?)),
+                }
+            }
+
+            Subnegotiation::SetParity(val) => {
+                match self.config.policy.parity.permits(&val) && port.set_parity(val)? {
+                    true => {
+                        self.line_settings.parity = val;
+                        Some(subnegotiation)
+                    }
+                    false => Some(Subnegotiation::SetParity(port.parity()?)),
+                }
+            }
+
+            Subnegotiation::SetStopSize(val) => {
+                match self.config.policy.stop_bits.permits(&val) && port.set_stop_bits(val)? {
+                    true => {
+                        self.line_settings.stop_bits = val;
+                        Some(subnegotiation)
+                    }
+                    false => Some(Subnegotiation::SetStopSize(port.stop_bits()?)),
+                }
+            }
+
+            Subnegotiation::SetControl(val) => self.handle_set_control(port, val)?,
+
+            // Flow control of the data sent to the client, which is not acknowledged
+            // since echoing it back would ask the client to suspend in turn
+            Subnegotiation::FlowControlSuspend => {
+                self.client.output_suspended = true;
+                None
+            }
+
+            Subnegotiation::FlowControlResume => {
+                self.client.output_suspended = false;
+                None
+            }
+
+            Subnegotiation::PurgeData(Purge::Unknown(_)) => None,
+
+            // A purge cannot be refused, so the client is answered as if it happened
+            Subnegotiation::PurgeData(val) => match self.handler.on_purge(val) {
+                Some(val) => Self::handle_purge_data(port, val)?,
+                None => Some(subnegotiation),
+            },
+
+            Subnegotiation::SetLinestateMask(val) => {
+                self.client.linestate_mask = val;
+                Some(subnegotiation)
+            }
+
+            Subnegotiation::SetModemStateMask(val) => {
+                // Let the client know the current state of the lines it is now interested in
+                self.client.modemstate_mask = val;
+                self.client.notify_modem_state = true;
+                Some(subnegotiation)
+            }
+
+            _ => None,
+        };
+
+        Ok(answer_opt)
+    }
+
+    fn handle_set_control<B: SerialBackend + ?Sized>(
+        &mut self,
+        port: &mut B,
+        val: Control,
+    ) -> Result<Option<Subnegotiation>, B::Error> {
+        // Changes the handler refuses are answered like the matching query
+        let val = match val {
+            Control::BreakOn | Control::BreakOff => {
+                match self.handler.on_break(val == Control::BreakOn) {
+                    Some(true) => Control::BreakOn,
+                    Some(false) => Control::BreakOff,
+                    None => Control::RequestBreak,
+                }
+            }
+            Control::NoFlowControl
+            | Control::XonXoffFlowControl
+            | Control::HardwareFlowControl
+            | Control::DcdFlowControl
+            | Control::DsrFlowControl => self
+                .handler
+                .on_control(val)
+                .unwrap_or(Control::RequestFlowControl),
+            Control::InboundNoFlowControl
+            | Control::InboundXonXoffFlowControl
+            | Control::InboundHardwareFlowControl
+            | Control::InboundDtrFlowControl => self
+                .handler
+                .on_control(val)
+                .unwrap_or(Control::RequestInboundFlowControl),
+            Control::DtrOn | Control::DtrOff => {
+                self.handler.on_control(val).unwrap_or(Control::RequestDtr)
+            }
+            Control::RtsOn | Control::RtsOff => {
+                self.handler.on_control(val).unwrap_or(Control::RequestRts)
+            }
+            _ => val,
+        };
+
+        match val {
+            Control::RequestFlowControl => Ok(Some(Subnegotiation::SetControl(
+                port.flow_control()?.to_control(),
+            ))),
+            Control::NoFlowControl | Control::XonXoffFlowControl | Control::HardwareFlowControl => {
+                let flow_control = FlowControl::from_control(val).unwrap();
+                match self.config.policy.flow_control.permits(&flow_control)
+                    && port.set_flow_control(flow_control)?
+                {
+                    true => {
+                        self.line_settings.flow_control = flow_control;
+                        Ok(Some(Subnegotiation::SetControl(val)))
+                    }
+                    false => Ok(Some(Subnegotiation::SetControl(
+                        port.flow_control()?.to_control(),
+                    ))),
+                }
+            }
+            Control::RequestBreak => match self.client.break_state {
+                true => Ok(Some(Subnegotiation::SetControl(Control::BreakOn))),
+                false => Ok(Some(Subnegotiation::SetControl(Control::BreakOff))),
+            },
+            Control::BreakOn => {
+                port.set_break(true)?;
+                self.client.break_state = true;
+                self.client.timed_break = false;
+                Ok(Some(Subnegotiation::SetControl(val)))
+            }
+            Control::BreakOff => {
+                port.set_break(false)?;
+                self.client.break_state = false;
+                self.client.timed_break = false;
+                Ok(Some(Subnegotiation::SetControl(val)))
+            }
+            Control::RequestDtr => match self.dtr_state {
+                true => Ok(Some(Subnegotiation::SetControl(Control::DtrOn))),
+                false => Ok(Some(Subnegotiation::SetControl(Control::DtrOff))),
+            },
+            Control::DtrOn => {
+                port.write_data_terminal_ready(true)?;
+                self.dtr_state = true;
+                Ok(Some(Subnegotiation::SetControl(val)))
+            }
+            Control::DtrOff => {
+                port.write_data_terminal_ready(false)?;
+                self.dtr_state = false;
+                Ok(Some(Subnegotiation::SetControl(val)))
+            }
+            Control::RequestRts => match self.rts_state {
+                true => Ok(Some(Subnegotiation::SetControl(Control::RtsOn))),
+                false => Ok(Some(Subnegotiation::SetControl(Control::RtsOff))),
+            },
+            Control::RtsOn => {
+                port.write_request_to_send(true)?;
+                self.rts_state = true;
+                Ok(Some(Subnegotiation::SetControl(val)))
+            }
+            Control::RtsOff => {
+                port.write_request_to_send(false)?;
+                self.rts_state = false;
+                Ok(Some(Subnegotiation::SetControl(val)))
+            }
+            // Serial ports only offer flow control for both directions at once,
+            // so the inbound one can only be what the outbound one already is
+            Control::RequestInboundFlowControl
+            | Control::InboundNoFlowControl
+            | Control::InboundXonXoffFlowControl
+            | Control::InboundHardwareFlowControl
+            | Control::InboundDtrFlowControl => Ok(Some(Subnegotiation::SetControl(
+                port.flow_control()?.to_inbound_control(),
+            ))),
+            // Flow control through DCD or DSR is refused with the current outbound one
+            Control::DcdFlowControl | Control::DsrFlowControl => Ok(Some(
+                Subnegotiation::SetControl(port.flow_control()?.to_control()),
+            )),
+            Control::Unknown(_) => Ok(None),
+        }
+    }
+
+    fn handle_purge_data<B: SerialBackend + ?Sized>(
+        port: &mut B,
+        val: Purge,
+    ) -> Result<Option<Subnegotiation>, B::Error> {
+        match val {
+            Purge::ReceiveBuffer => {
+                port.clear_input()?;
+                Ok(Some(Subnegotiation::PurgeData(val)))
+            }
+            Purge::TransmitBuffer => {
+                port.clear_output()?;
+                Ok(Some(Subnegotiation::PurgeData(val)))
+            }
+            Purge::BothBuffers => {
+                port.clear_input()?;
+                port.clear_output()?;
+                Ok(Some(Subnegotiation::PurgeData(val)))
+            }
+            Purge::Unknown(_) => Ok(None),
+        }
+    }
+}
@@ -1,8 +1,21 @@
 use crate::codes;
+use crate::com_port::{Control, LineState, ModemState, Parity, Purge, StopBits};
+use core::fmt;
 
 pub const MAX_DATA_SIZE: usize = 256;
 pub const NONDATA_SIZE: usize = 6;
-pub const MAX_SIZE: usize = MAX_DATA_SIZE + NONDATA_SIZE;
+// Every data byte may be an IAC which is doubled when serialized
+pub const MAX_SIZE: usize = 2 * MAX_DATA_SIZE + NONDATA_SIZE;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error {
+    TooShort(usize),
+    MissingIacSb,
+    MissingIacSe,
+    UnknownOption(u8),
+    WrongDataSize { option_code: u8, size: usize },
+    DataTooLong(usize),
+}
 
 // RFC2217 subnegotiation options, defined here: https://www.rfc-editor.org/rfc/rfc2217.html
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -13,16 +26,16 @@ pub enum Subnegotiation {
     },
     SetBaudRate(u32),
     SetDataSize(u8),
-    SetParity(u8),
-    SetStopSize(u8),
-    SetControl(u8),
-    NotifyLineState(u8),
-    NotifyModemState(u8),
+    SetParity(Parity),
+    SetStopSize(StopBits),
+    SetControl(Control),
+    NotifyLineState(LineState),
+    NotifyModemState(ModemState),
     FlowControlSuspend,
     FlowControlResume,
-    SetLinestateMask(u8),
-    SetModemStateMask(u8),
-    PurgeData(u8),
+    SetLinestateMask(LineState),
+    SetModemStateMask(ModemState),
+    PurgeData(Purge),
     Unsupported {
         base_option_code: u8,
         option_code: u8,
@@ -40,6 +53,26 @@ enum OptionKind {
 }
 
 impl Subnegotiation {
+    // The name RFC2217 gives the COM-PORT-OPTION command
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::SetSignature { .. } => "SIGNATURE",
+            Self::SetBaudRate(_) => "SET-BAUDRATE",
+            Self::SetDataSize(_) => "SET-DATASIZE",
+            Self::SetParity(_) => "SET-PARITY",
+            Self::SetStopSize(_) => "SET-STOPSIZE",
+            Self::SetControl(_) => "SET-CONTROL",
+            Self::NotifyLineState(_) => "NOTIFY-LINESTATE",
+            Self::NotifyModemState(_) => "NOTIFY-MODEMSTATE",
+            Self::FlowControlSuspend => "FLOWCONTROL-SUSPEND",
+            Self::FlowControlResume => "FLOWCONTROL-RESUME",
+            Self::SetLinestateMask(_) => "SET-LINESTATE-MASK",
+            Self::SetModemStateMask(_) => "SET-MODEMSTATE-MASK",
+            Self::PurgeData(_) => "PURGE-DATA",
+            Self::Unsupported { .. } => "unsupported",
+        }
+    }
+
     pub fn serialize_client(&self, buf: &mut [u8]) -> usize {
         self.serialize(buf, OptionKind::ClientToServer)
     }
@@ -48,135 +81,195 @@ impl Subnegotiation {
         self.serialize(buf, OptionKind::ServerToClient)
     }
 
+    // The size of the serialized subnegotiation, which is the same in both directions
+    pub fn serialized_size(&self) -> usize {
+        let mut data = [0; MAX_DATA_SIZE];
+        let (_, _, data_len) = self.payload(&mut data, OptionKind::ClientToServer);
+        let escapes = data[..data_len]
+            .iter()
+            .filter(|&&byte| byte == codes::IAC)
+            .count();
+        NONDATA_SIZE + data_len + escapes
+    }
+
     fn serialize(&self, buf: &mut [u8], option_kind: OptionKind) -> usize {
-        let start = |option_code: u8| -> [u8; 4] {
-            [
-                codes::IAC,
-                codes::SB,
-                codes::COM_PORT_OPTION,
-                match option_kind {
-                    OptionKind::ClientToServer => option_code,
-                    OptionKind::ServerToClient => option_code + 100,
-                },
-            ]
-        };
+        let mut data = [0; MAX_DATA_SIZE];
+        let (base_option_code, option_code, data_len) = self.payload(&mut data, option_kind);
 
-        let end = [codes::IAC, codes::SE];
+        buf[..4].copy_from_slice(&[codes::IAC, codes::SB, base_option_code, option_code]);
+        let mut i = 4;
+        for &byte in &data[..data_len] {
+            buf[i] = byte;
+            i += 1;
+            // Make sure to escape IAC bytes in the data
+            if byte == codes::IAC {
+                buf[i] = byte;
+                i += 1;
+            }
+        }
+        buf[i..i + 2].copy_from_slice(&[codes::IAC, codes::SE]);
+        i + 2
+    }
 
-        let mut subnegotiate = |option_code: u8, data: &[u8]| -> usize {
-            buf[..4].copy_from_slice(&start(option_code));
-            buf[4..4 + data.len()].copy_from_slice(data);
-            buf[4 + data.len()..NONDATA_SIZE + data.len()].copy_from_slice(&end);
-            NONDATA_SIZE + data.len()
+    // Writes the unescaped option data, returning the option codes and the data length
+    fn payload(&self, data: &mut [u8; MAX_DATA_SIZE], option_kind: OptionKind) -> (u8, u8, usize) {
+        let com_port = |option_code: u8| -> u8 {
+            match option_kind {
+                OptionKind::ClientToServer => option_code,
+                OptionKind::ServerToClient => option_code + 100,
+            }
+        };
+        let mut option = |option_code: u8, bytes: &[u8]| -> (u8, u8, usize) {
+            data[..bytes.len()].copy_from_slice(bytes);
+            (codes::COM_PORT_OPTION, com_port(option_code), bytes.len())
         };
 
         match *self {
-            Self::SetSignature { data, size } => {
-                buf[..4].copy_from_slice(&start(0));
-                let data_slice = &data[..size as usize];
-                let mut i = 4;
-                for byte in data_slice {
-                    buf[i] = *byte;
-                    i += 1;
-                    // Make sure to escape IAC bytes in the signature
-                    if *byte == codes::IAC {
-                        buf[i] = *byte;
-                        i += 1;
-                    }
-                }
-                buf[i..i + 2].copy_from_slice(&end);
-                i + 2
-            }
-
-            Self::SetBaudRate(baud) => subnegotiate(1, &u32::to_be_bytes(baud)),
-            Self::SetDataSize(data_size) => subnegotiate(2, &[data_size]),
-            Self::SetParity(parity) => subnegotiate(3, &[parity]),
-            Self::SetStopSize(stopsize) => subnegotiate(4, &[stopsize]),
-            Self::SetControl(control) => subnegotiate(5, &[control]),
-            Self::NotifyLineState(linestate) => subnegotiate(6, &[linestate]),
-            Self::NotifyModemState(modemstate) => subnegotiate(7, &[modemstate]),
-            Self::FlowControlSuspend => subnegotiate(8, &[]),
-            Self::FlowControlResume => subnegotiate(9, &[]),
-            Self::SetLinestateMask(linestate_mask) => subnegotiate(10, &[linestate_mask]),
-            Self::SetModemStateMask(modemstate_mask) => subnegotiate(11, &[modemstate_mask]),
-            Self::PurgeData(purge_data) => subnegotiate(12, &[purge_data]),
+            Self::SetSignature {
+                data: signature,
+                size,
+            } => option(0, &signature[..size as usize]),
+            Self::SetBaudRate(baud) => option(1, &u32::to_be_bytes(baud)),
+            Self::SetDataSize(data_size) => option(2, &[data_size]),
+            Self::SetParity(parity) => option(3, &[parity.to_u8()]),
+            Self::SetStopSize(stopsize) => option(4, &[stopsize.to_u8()]),
+            Self::SetControl(control) => option(5, &[control.to_u8()]),
+            Self::NotifyLineState(linestate) => option(6, &[linestate.to_u8()]),
+            Self::NotifyModemState(modemstate) => option(7, &[modemstate.to_u8()]),
+            Self::FlowControlSuspend => option(8, &[]),
+            Self::FlowControlResume => option(9, &[]),
+            Self::SetLinestateMask(linestate_mask) => option(10, &[linestate_mask.to_u8()]),
+            Self::SetModemStateMask(modemstate_mask) => option(11, &[modemstate_mask.to_u8()]),
+            Self::PurgeData(purge_data) => option(12, &[purge_data.to_u8()]),
             Self::Unsupported {
                 base_option_code,
                 option_code,
-                data,
+                data: unsupported_data,
                 data_cnt,
             } => {
-                buf[..4].copy_from_slice(&[codes::IAC, codes::SB, base_option_code, option_code]);
-                buf[4..4 + data_cnt as usize].copy_from_slice(&data[..data_cnt as usize]);
-                buf[4 + data_cnt as usize..NONDATA_SIZE + data_cnt as usize].copy_from_slice(&end);
-                NONDATA_SIZE + data_cnt as usize
+                data[..data_cnt as usize].copy_from_slice(&unsupported_data[..data_cnt as usize]);
+                (base_option_code, option_code, data_cnt as usize)
             }
         }
     }
 
     pub fn deserialize(buf: &[u8]) -> Self {
-        assert!(
-            buf[0] == codes::IAC
-                && buf[1] == codes::SB
-                && buf[buf.len() - 2] == codes::IAC
-                && buf[buf.len() - 1] == codes::SE
-        );
+        match Self::try_deserialize(buf) {
+            Ok(subnegotiation) => subnegotiation,
+            Err(error) => panic!("Not a valid subnegotiation: {:?}", error),
+        }
+    }
+
+    pub fn try_deserialize(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < NONDATA_SIZE {
+            return Err(Error::TooShort(buf.len()));
+        }
+        if buf[0] != codes::IAC || buf[1] != codes::SB {
+            return Err(Error::MissingIacSb);
+        }
+        if buf[buf.len() - 2] != codes::IAC || buf[buf.len() - 1] != codes::SE {
+            return Err(Error::MissingIacSe);
+        }
 
         let base_option_code = buf[2];
         let option_code = buf[3];
-        let data_len = buf.len() - NONDATA_SIZE;
-        let data = &buf[4..4 + data_len];
+
+        // Remove the IAC escapes from the option data
+        let escaped_data = &buf[4..buf.len() - 2];
+        let mut data_arr = [0; MAX_DATA_SIZE];
+        let mut data_len = 0;
+        let mut iac_occured = false;
+        for &byte in escaped_data {
+            if byte == codes::IAC {
+                iac_occured = !iac_occured;
+                if !iac_occured {
+                    continue;
+                }
+            }
+            // The size is stored in a u8, so the last data byte is unusable
+            if data_len == MAX_DATA_SIZE - 1 {
+                return Err(Error::DataTooLong(escaped_data.len()));
+            }
+            data_arr[data_len] = byte;
+            data_len += 1;
+        }
+        let data = &data_arr[..data_len];
+
+        // Checks that the option data is exactly as long as the option requires
+        let sized = |size: usize| -> Result<&[u8], Error> {
+            match data.len() == size {
+                true => Ok(data),
+                false => Err(Error::WrongDataSize {
+                    option_code,
+                    size: data.len(),
+                }),
+            }
+        };
 
         match base_option_code {
             codes::COM_PORT_OPTION => match option_code {
-                0 | 100 => {
-                    let mut data_no_escapes = [0; MAX_DATA_SIZE];
-                    let mut i = 0;
-                    let mut iac_occured = false;
-                    for &byte in data {
-                        if byte == codes::IAC {
-                            if iac_occured {
-                                iac_occured = false;
-                                continue;
-                            } else {
-                                iac_occured = true;
-                            }
-                        }
-                        data_no_escapes[i] = byte;
-                        i += 1;
-                    }
-                    Self::SetSignature {
-                        data: data_no_escapes,
-                        size: i as u8,
-                    }
-                }
+                0 | 100 => Ok(Self::SetSignature {
+                    data: data_arr,
+                    size: data_len as u8,
+                }),
                 1 | 101 => {
+                    let data = sized(4)?;
                     let baud_rate = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-                    Self::SetBaudRate(baud_rate)
+                    Ok(Self::SetBaudRate(baud_rate))
                 }
-                2 | 102 => Self::SetDataSize(data[0]),
-                3 | 103 => Self::SetParity(data[0]),
-                4 | 104 => Self::SetStopSize(data[0]),
-                5 | 105 => Self::SetControl(data[0]),
-                6 | 106 => Self::NotifyLineState(data[0]),
-                7 | 107 => Self::NotifyModemState(data[0]),
-                8 | 108 => Self::FlowControlSuspend,
-                9 | 109 => Self::FlowControlResume,
-                10 | 110 => Self::SetLinestateMask(data[0]),
-                11 | 111 => Self::SetModemStateMask(data[0]),
-                12 | 112 => Self::PurgeData(data[0]),
-                _ => panic!("Option code is not a Com Port option code"),
+                2 | 102 => Ok(Self::SetDataSize(sized(1)?[0])),
+                3 | 103 => Ok(Self::SetParity(Parity::from_u8(sized(1)?[0]))),
+                4 | 104 => Ok(Self::SetStopSize(StopBits::from_u8(sized(1)?[0]))),
+                5 | 105 => Ok(Self::SetControl(Control::from_u8(sized(1)?[0]))),
+                6 | 106 => Ok(Self::NotifyLineState(LineState::from_u8(sized(1)?[0]))),
+                7 | 107 => Ok(Self::NotifyModemState(ModemState::from_u8(sized(1)?[0]))),
+                8 | 108 => sized(0).map(|_| Self::FlowControlSuspend),
+                9 | 109 => sized(0).map(|_| Self::FlowControlResume),
+                10 | 110 => Ok(Self::SetLinestateMask(LineState::from_u8(sized(1)?[0]))),
+                11 | 111 => Ok(Self::SetModemStateMask(ModemState::from_u8(sized(1)?[0]))),
+                12 | 112 => Ok(Self::PurgeData(Purge::from_u8(sized(1)?[0]))),
+                _ => Err(Error::UnknownOption(option_code)),
             },
-            _ => {
-                let mut data_arr = [0; MAX_DATA_SIZE];
-                data_arr.copy_from_slice(data);
-                Self::Unsupported {
-                    base_option_code: base_option_code,
-                    option_code: option_code,
-                    data: data_arr,
-                    data_cnt: data_len as u8,
-                }
+            _ => Ok(Self::Unsupported {
+                base_option_code,
+                option_code,
+                data: data_arr,
+                data_cnt: data_len as u8,
+            }),
+        }
+    }
+}
+
+// The command name followed by its value, meant for logs
+impl fmt::Display for Subnegotiation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())?;
+        match self {
+            Self::SetSignature { data, size } => {
+                write!(f, " \"{}\"", data[..*size as usize].escape_ascii())
             }
+            Self::SetBaudRate(baud_rate) => write!(f, " {baud_rate}"),
+            Self::SetDataSize(data_bits) => write!(f, " {data_bits}"),
+            Self::SetParity(parity) => write!(f, " {parity:?}"),
+            Self::SetStopSize(stop_bits) => write!(f, " {stop_bits:?}"),
+            Self::SetControl(control) => write!(f, " {control:?}"),
+            Self::NotifyLineState(state) | Self::SetLinestateMask(state) => {
+                write!(f, " {:#04x}", state.to_u8())
+            }
+            Self::NotifyModemState(state) | Self::SetModemStateMask(state) => {
+                write!(f, " {:#04x}", state.to_u8())
+            }
+            Self::FlowControlSuspend | Self::FlowControlResume => Ok(()),
+            Self::PurgeData(purge) => write!(f, " {purge:?}"),
+            Self::Unsupported {
+                base_option_code,
+                option_code,
+                data_cnt,
+                ..
+            } => write!(
+                f,
+                " option {base_option_code} command {option_code} with {data_cnt} bytes"
+            ),
         }
     }
 }
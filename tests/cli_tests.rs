@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+// Runs the server binary with --check_config and returns the ports it would serve
+fn check_config(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(args)
+        .arg("--check_config")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn write_config(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rfc2217-{}-{name}", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn test_config_defaults() {
+    assert_eq!(check_config(&[]), "/dev/ttyUSB0 on 127.0.0.1:7878\n");
+
+    // Without any ports listed, the file defaults make up the single port
+    let path = write_config(
+        "defaults.toml",
+        "[defaults]\ndevice = \"/dev/ttyACM7\"\ntcp_port = 9999\n",
+    );
+    let config = path.to_str().unwrap();
+    assert_eq!(
+        check_config(&["--config", config]),
+        "/dev/ttyACM7 on 127.0.0.1:9999\n"
+    );
+    // The command line still overrides them
+    assert_eq!(
+        check_config(&["--config", config, "--tcp_port", "7000"]),
+        "/dev/ttyACM7 on 127.0.0.1:7000\n"
+    );
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,118 @@
+use rfc2217_rs::parser::Event;
+use rfc2217_rs::*;
+use serialport::SerialPort;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// A minimal RFC2217 server which accepts every option, acknowledges every
+// subnegotiation and echoes the data back
+fn echo_server(mut conn: TcpStream) {
+    let mut parser = Parser::new();
+    let mut tcp_data = [0; 256];
+    let mut answer = [0; subnegotiation::MAX_SIZE];
+    loop {
+        let bytes_read = match conn.read(&mut tcp_data) {
+            Ok(0) | Err(_) => return,
+            Ok(bytes_read) => bytes_read,
+        };
+        for &byte in &tcp_data[..bytes_read] {
+            let size = match parser.process_byte(byte).unwrap() {
+                Some(Event::Data(byte)) if byte == 255 => {
+                    answer[..2].copy_from_slice(&[byte, byte]);
+                    2
+                }
+                Some(Event::Data(byte)) => {
+                    answer[0] = byte;
+                    1
+                }
+                Some(Event::Negotiation(Negotiation {
+                    intent: negotiation::Intent::Will,
+                    option,
+                })) => {
+                    Negotiation {
+                        intent: negotiation::Intent::Do,
+                        option,
+                    }
+                    .serialize(&mut answer);
+                    negotiation::SIZE
+                }
+                Some(Event::Subnegotiation(subnegotiation)) => {
+                    subnegotiation.serialize_server(&mut answer)
+                }
+                _ => 0,
+            };
+            conn.write_all(&answer[..size]).unwrap();
+        }
+    }
+}
+
+fn connect_client() -> Client {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || echo_server(listener.accept().unwrap().0));
+    Client::new(addr, 115200).unwrap()
+}
+
+#[test]
+fn test_client_settings() {
+    let mut client = connect_client();
+    assert_eq!(client.baud_rate().unwrap(), 115200);
+
+    client.set_baud_rate(9600).unwrap();
+    client.set_parity(serialport::Parity::Even).unwrap();
+    client.write_data_terminal_ready(false).unwrap();
+    client.set_break().unwrap();
+    client.clear_break().unwrap();
+
+    assert_eq!(client.baud_rate().unwrap(), 9600);
+    assert_eq!(client.parity().unwrap(), serialport::Parity::Even);
+}
+
+#[test]
+fn test_client_data_containing_iac() {
+    let mut client = connect_client();
+    client.set_timeout(Duration::from_secs(1)).unwrap();
+
+    let sent = [1, 255, 2, 255, 255, 3];
+    client.write_all(&sent).unwrap();
+
+    let mut received = [0; 6];
+    client.read_exact(&mut received).unwrap();
+    assert_eq!(received, sent);
+}
+
+#[test]
+fn test_client_com_port_disabled() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Accepts the options, then stops using COM-PORT-OPTION instead of answering
+    thread::spawn(move || {
+        let mut conn = listener.accept().unwrap().0;
+        let mut parser = Parser::new();
+        let mut byte = [0];
+        let mut answer = [0; negotiation::SIZE];
+        while conn.read_exact(&mut byte).is_ok() {
+            let intent = match parser.process_byte(byte[0]).unwrap() {
+                Some(Event::Negotiation(Negotiation {
+                    intent: negotiation::Intent::Will,
+                    option,
+                })) => (negotiation::Intent::Do, option),
+                Some(Event::Subnegotiation(_)) => {
+                    (negotiation::Intent::Dont, negotiation::Option::ComPort)
+                }
+                _ => continue,
+            };
+            Negotiation {
+                intent: intent.0,
+                option: intent.1,
+            }
+            .serialize(&mut answer);
+            conn.write_all(&answer).unwrap();
+        }
+    });
+
+    let error = io::Error::from(Client::new(addr, 115200).err().unwrap());
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+}
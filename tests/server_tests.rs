@@ -0,0 +1,1430 @@
+use rfc2217_rs::backend::{FlowControl, SerialBackend};
+use rfc2217_rs::com_port::{Control, LineState, ModemState, Parity, Purge, StopBits};
+use rfc2217_rs::parser::Event;
+use rfc2217_rs::*;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Default)]
+struct MockState {
+    baud_rate: u32,
+    dtr: bool,
+    rts: bool,
+    break_state: bool,
+    parity: Option<Parity>,
+    carrier_detect: bool,
+    // Unplugged, reading the port fails
+    lost: bool,
+    // Writing to the port fails without the device being gone
+    write_error: Option<io::ErrorKind>,
+    // The output buffer is stuck full, writes take none of the data
+    stuck: bool,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    backlog: u32,
+}
+
+// A serial backend recording the settings applied by the server
+#[derive(Clone, Default)]
+struct MockBackend(Arc<Mutex<MockState>>);
+
+impl SerialBackend for MockBackend {
+    type Error = io::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        if state.lost {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let size = state.input.len().min(buf.len());
+        buf[..size].copy_from_slice(&state.input[..size]);
+        state.input.drain(..size);
+        Ok(size)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        if let Some(kind) = state.write_error {
+            return Err(kind.into());
+        }
+        if state.stuck {
+            return Ok(0);
+        }
+        state.output.extend_from_slice(buf);
+        // The data waits in the output buffer until it is flushed
+        state.backlog += buf.len() as u32;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().backlog = 0;
+        Ok(())
+    }
+
+    fn baud_rate(&mut self) -> io::Result<u32> {
+        Ok(self.0.lock().unwrap().baud_rate)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<bool> {
+        self.0.lock().unwrap().baud_rate = baud_rate;
+        Ok(true)
+    }
+
+    fn data_bits(&mut self) -> io::Result<u8> {
+        Ok(8)
+    }
+
+    fn set_data_bits(&mut self, data_bits: u8) -> io::Result<bool> {
+        Ok(data_bits == 8)
+    }
+
+    fn parity(&mut self) -> io::Result<Parity> {
+        Ok(self.0.lock().unwrap().parity.unwrap_or(Parity::None))
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> io::Result<bool> {
+        let supported = matches!(parity, Parity::None | Parity::Space);
+        if supported {
+            self.0.lock().unwrap().parity = Some(parity);
+        }
+        Ok(supported)
+    }
+
+    fn stop_bits(&mut self) -> io::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> io::Result<bool> {
+        Ok(stop_bits == StopBits::One)
+    }
+
+    fn flow_control(&mut self) -> io::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> io::Result<bool> {
+        Ok(flow_control == FlowControl::None)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        self.0.lock().unwrap().dtr = level;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        self.0.lock().unwrap().rts = level;
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_data_set_ready(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_ring_indicator(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> io::Result<bool> {
+        Ok(self.0.lock().unwrap().carrier_detect)
+    }
+
+    fn set_break(&mut self, level: bool) -> io::Result<()> {
+        self.0.lock().unwrap().break_state = level;
+        Ok(())
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().input.clear();
+        Ok(())
+    }
+
+    fn clear_output(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().output.clear();
+        Ok(())
+    }
+
+    fn bytes_to_read(&mut self) -> io::Result<u32> {
+        Ok(self.0.lock().unwrap().input.len() as u32)
+    }
+
+    fn bytes_to_write(&mut self) -> io::Result<u32> {
+        Ok(self.0.lock().unwrap().backlog)
+    }
+}
+
+fn connect_server(backend: MockBackend) -> TcpStream {
+    connect(spawn_server(
+        Server::with_backend(backend, "127.0.0.1:0").unwrap(),
+    ))
+}
+
+// Runs the server on its own thread until it fails
+fn spawn_server<H: ServerHandler + Send + 'static>(
+    mut server: Server<MockBackend, H>,
+) -> SocketAddr {
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || while server.run().is_ok() {});
+    addr
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let conn = TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    conn
+}
+
+// Sends a subnegotiation and waits for the server's answer
+fn request(conn: &mut TcpStream, subnegotiation: Subnegotiation) -> Subnegotiation {
+    let mut buf = [0; subnegotiation::MAX_SIZE];
+    let size = subnegotiation.serialize_client(&mut buf);
+    conn.write_all(&buf[..size]).unwrap();
+    receive(conn)
+}
+
+// Waits for the next subnegotiation from the server
+fn receive(conn: &mut TcpStream) -> Subnegotiation {
+    let mut parser = Parser::new();
+    let mut byte = [0];
+    loop {
+        conn.read_exact(&mut byte).unwrap();
+        if let Some(Event::Subnegotiation(answer)) = parser.process_byte(byte[0]).unwrap() {
+            return answer;
+        }
+    }
+}
+
+#[test]
+fn test_server_set_control() {
+    let backend = MockBackend::default();
+    let mut conn = connect_server(backend.clone());
+
+    for (control, answer) in [
+        (Control::DtrOn, Control::DtrOn),
+        (Control::RtsOn, Control::RtsOn),
+        (Control::BreakOn, Control::BreakOn),
+        (Control::RequestBreak, Control::BreakOn),
+        (Control::HardwareFlowControl, Control::NoFlowControl),
+    ] {
+        assert_eq!(
+            request(&mut conn, Subnegotiation::SetControl(control)),
+            Subnegotiation::SetControl(answer)
+        );
+    }
+
+    let state = backend.0.lock().unwrap();
+    assert!(state.dtr);
+    assert!(state.rts);
+    assert!(state.break_state);
+}
+
+// Offers COM-PORT-OPTION and waits for the server's answer
+fn negotiate_com_port(conn: &mut TcpStream) -> Negotiation {
+    let mut buf = [0; negotiation::SIZE];
+    Negotiation {
+        intent: negotiation::Intent::Will,
+        option: negotiation::Option::ComPort,
+    }
+    .serialize(&mut buf);
+    conn.write_all(&buf).unwrap();
+    let mut parser = Parser::new();
+    let mut byte = [0];
+    loop {
+        conn.read_exact(&mut byte).unwrap();
+        if let Some(Event::Negotiation(answer)) = parser.process_byte(byte[0]).unwrap() {
+            return answer;
+        }
+    }
+}
+
+#[test]
+fn test_server_next_client() {
+    let backend = MockBackend::default();
+    let addr = spawn_server(Server::with_backend(backend.clone(), "127.0.0.1:0").unwrap());
+    let accepted = Negotiation {
+        intent: negotiation::Intent::Do,
+        option: negotiation::Option::ComPort,
+    };
+
+    let mut conn = connect(addr);
+    assert_eq!(negotiate_com_port(&mut conn), accepted);
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(57600)),
+        Subnegotiation::SetBaudRate(57600)
+    );
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetParity(Parity::Space)),
+        Subnegotiation::SetParity(Parity::Space)
+    );
+    drop(conn);
+
+    // The server keeps listening, the next client negotiates from scratch
+    // and finds the port back in the initial settings
+    let mut conn = connect(addr);
+    assert_eq!(negotiate_com_port(&mut conn), accepted);
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(0)),
+        Subnegotiation::SetBaudRate(9600)
+    );
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetParity(Parity::Request)),
+        Subnegotiation::SetParity(Parity::None)
+    );
+    let state = backend.0.lock().unwrap();
+    assert_eq!(state.baud_rate, 9600);
+    assert_eq!(state.parity, Some(Parity::None));
+}
+
+#[test]
+fn test_server_line_settings() {
+    let backend = MockBackend::default();
+    let mut conn = connect_server(backend.clone());
+
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(115200)),
+        Subnegotiation::SetBaudRate(115200)
+    );
+    assert_eq!(backend.0.lock().unwrap().baud_rate, 115200);
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetDataSize(5)),
+        Subnegotiation::SetDataSize(8)
+    );
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetParity(Parity::Mark)),
+        Subnegotiation::SetParity(Parity::None)
+    );
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetParity(Parity::Space)),
+        Subnegotiation::SetParity(Parity::Space)
+    );
+    assert_eq!(
+        request(
+            &mut conn,
+            Subnegotiation::SetStopSize(StopBits::OnePointFive)
+        ),
+        Subnegotiation::SetStopSize(StopBits::One)
+    );
+}
+
+#[test]
+fn test_client_parity_option() {
+    use serialport::SerialPort;
+
+    let backend = MockBackend::default();
+    let addr = spawn_server(Server::with_backend(backend.clone(), "127.0.0.1:0").unwrap());
+    let mut client = Client::new(addr, 9600).unwrap();
+
+    // Settings serialport cannot represent go through the option values
+    client.set_parity_option(Parity::Space).unwrap();
+    assert_eq!(client.parity_option(), Parity::Space);
+    assert_eq!(backend.0.lock().unwrap().parity, Some(Parity::Space));
+    assert!(SerialPort::parity(&client).is_err());
+
+    // Refused settings report the one in effect
+    assert!(matches!(
+        client.set_parity_option(Parity::Mark),
+        Err(client::Error::ParityRefused(Parity::Space))
+    ));
+    assert!(matches!(
+        client.set_stop_bits_option(StopBits::OnePointFive),
+        Err(client::Error::StopBitsRefused(StopBits::One))
+    ));
+    SerialPort::set_parity(&mut client, serialport::Parity::None).unwrap();
+    assert_eq!(
+        SerialPort::parity(&client).unwrap(),
+        serialport::Parity::None
+    );
+}
+
+#[test]
+fn test_multi_server() {
+    use std::sync::mpsc;
+
+    let first = MockBackend::default();
+    let second = MockBackend::default();
+    let first_server = Server::with_backend(first.clone(), "127.0.0.1:0").unwrap();
+    let second_server = Server::with_backend(second.clone(), "127.0.0.1:0").unwrap();
+    let first_addr = first_server.local_addr().unwrap();
+    let second_addr = second_server.local_addr().unwrap();
+    let multi_server = MultiServer::new()
+        .add_server("first", first_server)
+        .add(
+            Server::builder(),
+            "/dev/rfc2217-missing",
+            "127.0.0.1:0".parse().unwrap(),
+        )
+        .add_server("second", second_server);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        multi_server.run(|name, error| sender.send((name.to_owned(), error)).unwrap())
+    });
+
+    // The missing device fails on its own, the other bridges keep serving
+    let (name, error) = receiver.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert_eq!(name, "/dev/rfc2217-missing");
+    assert!(matches!(error, server::Error::SerialInit(_)));
+    for (addr, backend, baud_rate) in [(first_addr, first, 19200), (second_addr, second, 38400)] {
+        let mut conn = connect(addr);
+        assert_eq!(
+            request(&mut conn, Subnegotiation::SetBaudRate(baud_rate)),
+            Subnegotiation::SetBaudRate(baud_rate)
+        );
+        assert_eq!(backend.0.lock().unwrap().baud_rate, baud_rate);
+    }
+}
+
+#[test]
+fn test_multi_server_client_stops_reading() {
+    use std::sync::mpsc;
+
+    let backend = MockBackend::default();
+    let server = Server::builder()
+        .write_timeout(Duration::from_millis(100))
+        .send_buffer_size(4096)
+        .buffer_size(4096)
+        .build_with_backend(backend.clone(), "127.0.0.1:0")
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let multi_server = MultiServer::new().add_server("stalled", server);
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        multi_server.run(|name, error| sender.send((name.to_owned(), error)).unwrap())
+    });
+
+    // The bridge drops the client that stopped reading and keeps serving
+    let _stalled = stall_client(addr, &backend);
+    let mut conn = connect(addr);
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(0)),
+        Subnegotiation::SetBaudRate(9600)
+    );
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_server_purge_data() {
+    let backend = MockBackend::default();
+    let mut conn = connect_server(backend.clone());
+
+    backend
+        .0
+        .lock()
+        .unwrap()
+        .output
+        .extend_from_slice(b"pending");
+    assert_eq!(
+        request(&mut conn, Subnegotiation::PurgeData(Purge::TransmitBuffer)),
+        Subnegotiation::PurgeData(Purge::TransmitBuffer)
+    );
+    assert!(backend.0.lock().unwrap().output.is_empty());
+}
+
+// A handler capping the baud rate, refusing to raise DTR and recording what it is told
+#[derive(Clone, Default)]
+struct MockHandler(Arc<Mutex<Vec<String>>>);
+
+impl ServerHandler for MockHandler {
+    fn on_connect(&mut self) {
+        self.0.lock().unwrap().push("connect".into());
+    }
+
+    fn on_baud_rate(&mut self, baud_rate: u32) -> Option<u32> {
+        Some(baud_rate.min(115200))
+    }
+
+    fn on_control(&mut self, control: Control) -> Option<Control> {
+        match control {
+            Control::DtrOn => None,
+            control => Some(control),
+        }
+    }
+
+    fn on_break(&mut self, break_state: bool) -> Option<bool> {
+        self.0.lock().unwrap().push(format!("break {break_state}"));
+        Some(break_state)
+    }
+
+    fn on_data(&mut self, direction: handler::Direction, data: &[u8]) {
+        let data = String::from_utf8_lossy(data);
+        self.0.lock().unwrap().push(format!("{direction:?} {data}"));
+    }
+}
+
+#[test]
+fn test_server_handler() {
+    let backend = MockBackend::default();
+    let handler = MockHandler::default();
+    let server = Server::builder()
+        .handler(handler.clone())
+        .build_with_backend(backend.clone(), "127.0.0.1:0")
+        .unwrap();
+    let addr = spawn_server(server);
+    let mut conn = connect(addr);
+
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(921600)),
+        Subnegotiation::SetBaudRate(115200)
+    );
+    assert_eq!(backend.0.lock().unwrap().baud_rate, 115200);
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetControl(Control::DtrOff)),
+        Subnegotiation::SetControl(Control::DtrOff)
+    );
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetControl(Control::DtrOn)),
+        Subnegotiation::SetControl(Control::DtrOff)
+    );
+    assert!(!backend.0.lock().unwrap().dtr);
+    // Accepted by the handler, but the port has no such flow control
+    assert_eq!(
+        request(
+            &mut conn,
+            Subnegotiation::SetControl(Control::DcdFlowControl)
+        ),
+        Subnegotiation::SetControl(Control::NoFlowControl)
+    );
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetControl(Control::BreakOn)),
+        Subnegotiation::SetControl(Control::BreakOn)
+    );
+
+    conn.write_all(b"hi").unwrap();
+    while backend.0.lock().unwrap().output != b"hi" {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(
+        *handler.0.lock().unwrap(),
+        ["connect", "break true", "ToSerial hi"]
+    );
+}
+
+#[test]
+fn test_server_flow_control_suspend() {
+    let backend = MockBackend::default();
+    let mut conn = connect_server(backend.clone());
+
+    // The data piles up in the serial output buffer, the client is asked to stop sending
+    let data = vec![b'a'; session::DEFAULT_SUSPEND_THRESHOLD as usize + 1];
+    conn.write_all(&data).unwrap();
+    assert_eq!(receive(&mut conn), Subnegotiation::FlowControlSuspend);
+    assert!(backend.0.lock().unwrap().backlog > session::DEFAULT_SUSPEND_THRESHOLD);
+}
+
+#[test]
+fn test_server_client_stops_reading() {
+    let backend = MockBackend::default();
+    let server = Server::builder()
+        .write_timeout(Duration::from_millis(100))
+        .send_buffer_size(4096)
+        .buffer_size(4096)
+        .build_with_backend(backend.clone(), "127.0.0.1:0")
+        .unwrap();
+    let addr = spawn_server(server);
+
+    let _stalled = stall_client(addr, &backend);
+
+    // The stalled client is disconnected once the write times out and the next one is served
+    let mut conn = connect(addr);
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(0)),
+        Subnegotiation::SetBaudRate(9600)
+    );
+}
+
+// Connects a client that never reads and lets the serial data pile up until the server
+// can send no more of it, the data left is dropped afterwards
+fn stall_client(addr: SocketAddr, backend: &MockBackend) -> TcpStream {
+    backend.0.lock().unwrap().input = vec![b'a'; 32 << 20];
+    let stalled = connect(addr);
+    let mut remaining = 0;
+    loop {
+        thread::sleep(Duration::from_millis(50));
+        let input = backend.0.lock().unwrap().input.len();
+        if input == remaining {
+            break;
+        }
+        remaining = input;
+    }
+    assert!(remaining > 0);
+    backend.0.lock().unwrap().input.clear();
+    stalled
+}
+
+#[test]
+fn test_server_builder() {
+    let backend = MockBackend::default();
+    let server = Server::builder()
+        .baud_rate(115200)
+        .signature("mock")
+        .build_with_backend(backend.clone(), "127.0.0.1:0")
+        .unwrap();
+    assert_eq!(backend.0.lock().unwrap().baud_rate, 115200);
+
+    let addr = spawn_server(server);
+    let mut conn = connect(addr);
+    let mut signature = [0; subnegotiation::MAX_DATA_SIZE];
+    signature[..4].copy_from_slice(b"mock");
+    assert_eq!(
+        request(
+            &mut conn,
+            Subnegotiation::SetSignature {
+                data: [0; subnegotiation::MAX_DATA_SIZE],
+                size: 0
+            }
+        ),
+        Subnegotiation::SetSignature {
+            data: signature,
+            size: 4
+        }
+    );
+
+    let result = Server::builder()
+        .data_bits(7)
+        .build_with_backend(MockBackend::default(), "127.0.0.1:0");
+    assert!(matches!(
+        result,
+        Err(server::Error::UnsupportedSettings(settings)) if settings.data_bits == 7
+    ));
+}
+
+#[test]
+fn test_server_allow_clients() {
+    let server = Server::builder()
+        .allow_clients(["10.0.0.0/8".parse().unwrap()])
+        .build_with_backend(MockBackend::default(), "127.0.0.1:0")
+        .unwrap();
+    let addr = spawn_server(server);
+
+    // Clients from other networks are disconnected without an answer
+    let mut conn = connect(addr);
+    let mut packet = [0; subnegotiation::MAX_SIZE];
+    let size = Subnegotiation::SetBaudRate(0).serialize_client(&mut packet);
+    let _ = conn.write_all(&packet[..size]);
+    let mut answer = [0; 1];
+    assert!(!matches!(conn.read(&mut answer), Ok(1)));
+}
+
+#[test]
+fn test_server_reopen() {
+    let backend = MockBackend::default();
+    backend.0.lock().unwrap().carrier_detect = true;
+    let opened = backend.clone();
+    let server = Server::builder()
+        .reopen(true)
+        .reopen_delay(Duration::from_millis(10), Duration::from_millis(40))
+        .build_with_opener(
+            move || {
+                // Like serial ports opened exclusively, the port cannot be opened twice
+                if Arc::strong_count(&opened.0) > 2 {
+                    return Err(io::Error::other("the port is still open"));
+                }
+                match opened.0.lock().unwrap().lost {
+                    true => Err(io::ErrorKind::NotFound.into()),
+                    false => Ok(opened.clone()),
+                }
+            },
+            "127.0.0.1:0",
+        )
+        .unwrap();
+    let addr = spawn_server(server);
+    let mut conn = connect(addr);
+    let carrier_detect = |conn: &mut TcpStream| loop {
+        if let Subnegotiation::NotifyModemState(state) = receive(conn) {
+            return state.contains(ModemState::CD);
+        }
+    };
+    assert!(carrier_detect(&mut conn));
+
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(57600)),
+        Subnegotiation::SetBaudRate(57600)
+    );
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetControl(Control::DtrOff)),
+        Subnegotiation::SetControl(Control::DtrOff)
+    );
+
+    // The device comes back with its defaults, the client's settings are applied again
+    {
+        let mut state = backend.0.lock().unwrap();
+        state.lost = true;
+        state.baud_rate = 9600;
+        state.dtr = true;
+    }
+    assert!(!carrier_detect(&mut conn));
+    backend.0.lock().unwrap().lost = false;
+    assert!(carrier_detect(&mut conn));
+    {
+        let state = backend.0.lock().unwrap();
+        assert_eq!(state.baud_rate, 57600);
+        assert!(!state.dtr);
+    }
+
+    // The same connection keeps working
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(0)),
+        Subnegotiation::SetBaudRate(57600)
+    );
+}
+
+#[test]
+fn test_server_client_leaves_lost_port() {
+    let backend = MockBackend::default();
+    backend.0.lock().unwrap().carrier_detect = true;
+    let opened = backend.clone();
+    let server = Server::builder()
+        .baud_rate(19200)
+        .reopen(true)
+        .reopen_delay(Duration::from_millis(10), Duration::from_millis(40))
+        .build_with_opener(
+            move || match opened.0.lock().unwrap().lost {
+                true => Err(io::ErrorKind::NotFound.into()),
+                false => Ok(opened.clone()),
+            },
+            "127.0.0.1:0",
+        )
+        .unwrap();
+    let addr = spawn_server(server);
+
+    let carrier_detect = |conn: &mut TcpStream| loop {
+        if let Subnegotiation::NotifyModemState(state) = receive(conn) {
+            return state.contains(ModemState::CD);
+        }
+    };
+
+    let mut conn = connect(addr);
+    assert!(carrier_detect(&mut conn));
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(57600)),
+        Subnegotiation::SetBaudRate(57600)
+    );
+    backend.0.lock().unwrap().lost = true;
+    assert!(!carrier_detect(&mut conn));
+    // The client leaves while the port is still gone, the next one starts with the defaults
+    drop(conn);
+    thread::sleep(Duration::from_millis(100));
+    backend.0.lock().unwrap().lost = false;
+
+    let mut conn = connect(addr);
+    assert!(carrier_detect(&mut conn));
+    assert_eq!(
+        request(&mut conn, Subnegotiation::SetBaudRate(0)),
+        Subnegotiation::SetBaudRate(19200)
+    );
+    assert_eq!(backend.0.lock().unwrap().baud_rate, 19200);
+}
+
+#[test]
+fn test_server_reopen_only_lost_port() {
+    let backend = MockBackend::default();
+    backend.0.lock().unwrap().write_error = Some(io::ErrorKind::TimedOut);
+    let opens = Arc::new(Mutex::new(0));
+    let (opened, counted) = (backend.clone(), opens.clone());
+    let mut server = Server::builder()
+        .reopen(true)
+        .build_with_opener(
+            move || {
+                *counted.lock().unwrap() += 1;
+                Ok(opened.clone())
+            },
+            "127.0.0.1:0",
+        )
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let server = thread::spawn(move || loop {
+        if let Err(error) = server.run() {
+            return error;
+        }
+    });
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.write_all(b"data").unwrap();
+
+    // A timed out write is not the device going away, the error ends the server
+    match server.join().unwrap() {
+        server::Error::Serial(error) => assert_eq!(error.kind(), io::ErrorKind::TimedOut),
+        error => panic!("unexpected error {error:?}"),
+    }
+    assert_eq!(*opens.lock().unwrap(), 1);
+}
+
+#[test]
+fn test_session_reset_on_disconnect() {
+    let mut backend = MockBackend::default();
+    let mut config = session::Config::new();
+    config.port_settings.baud_rate = 19200;
+
+    let mut session = Session::with_config(config);
+    backend.set_baud_rate(115200).unwrap();
+    session.disconnect(&mut backend).unwrap();
+    assert_eq!(backend.0.lock().unwrap().baud_rate, 19200);
+
+    config.reset_on_disconnect = false;
+    let mut session = Session::with_config(config);
+    backend.set_baud_rate(115200).unwrap();
+    session.disconnect(&mut backend).unwrap();
+    assert_eq!(backend.0.lock().unwrap().baud_rate, 115200);
+}
+
+// Passes a subnegotiation to the session and parses its answer
+fn session_request<H: ServerHandler>(
+    session: &mut Session<H>,
+    backend: &mut MockBackend,
+    request: Subnegotiation,
+) -> Subnegotiation {
+    let mut packet = [0; subnegotiation::MAX_SIZE];
+    let mut serial_out = [0; subnegotiation::MAX_SIZE];
+    let mut network_out = [0; session::MAX_ANSWER_SIZE];
+    let size = request.serialize_client(&mut packet);
+    let progress = session
+        .process_network_data(backend, &packet[..size], &mut serial_out, &mut network_out)
+        .unwrap();
+    let mut parser = Parser::new();
+    network_out[..progress.network_produced]
+        .iter()
+        .find_map(|&byte| match parser.process_byte(byte).unwrap() {
+            Some(Event::Subnegotiation(answer)) => Some(answer),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_session_signature() {
+    let mut backend = MockBackend::default();
+    let mut session = Session::new();
+
+    // An empty signature queries the server's one
+    let query = Subnegotiation::SetSignature {
+        data: [0; subnegotiation::MAX_DATA_SIZE],
+        size: 0,
+    };
+    let Subnegotiation::SetSignature { data, size } =
+        session_request(&mut session, &mut backend, query)
+    else {
+        panic!("Not a signature");
+    };
+    assert_eq!(
+        &data[..size as usize],
+        session::DEFAULT_SIGNATURE.as_bytes()
+    );
+    assert_eq!(session.client_signature(), None);
+
+    // The longest signature, full of bytes that need escaping
+    let mut data = [255; subnegotiation::MAX_DATA_SIZE];
+    data[255] = 0;
+    let signature = Subnegotiation::SetSignature { data, size: 255 };
+    let mut packet = [0; subnegotiation::MAX_SIZE];
+    let mut serial_out = [0; subnegotiation::MAX_SIZE];
+    let mut network_out = [0; session::MAX_ANSWER_SIZE];
+    let size = signature.serialize_client(&mut packet);
+    let progress = session
+        .process_network_data(
+            &mut backend,
+            &packet[..size],
+            &mut serial_out,
+            &mut network_out,
+        )
+        .unwrap();
+    // The client's signature is stored, not echoed back
+    assert_eq!(progress.network_produced, 0);
+    assert_eq!(session.client_signature(), Some(&[255; 255][..]));
+
+    session.disconnect(&mut backend).unwrap();
+    assert_eq!(session.client_signature(), None);
+
+    let mut config = session::Config::new();
+    config.set_signature(&[b'x'; 300]);
+    assert_eq!(config.signature(), &[b'x'; 255][..]);
+}
+
+#[test]
+fn test_session_set_control_queries() {
+    let mut backend = MockBackend::default();
+    let mut session = Session::new();
+
+    // Line states are reported as set, not read back from the modem lines
+    for (request, answer) in [
+        (Control::RequestDtr, Control::DtrOn),
+        (Control::DtrOff, Control::DtrOff),
+        (Control::RequestDtr, Control::DtrOff),
+        (Control::RtsOff, Control::RtsOff),
+        (Control::RtsOn, Control::RtsOn),
+        (Control::RequestRts, Control::RtsOn),
+        // Unsupported flow control is refused with the current setting
+        (
+            Control::RequestInboundFlowControl,
+            Control::InboundNoFlowControl,
+        ),
+        (
+            Control::InboundHardwareFlowControl,
+            Control::InboundNoFlowControl,
+        ),
+        (
+            Control::InboundDtrFlowControl,
+            Control::InboundNoFlowControl,
+        ),
+        (Control::DcdFlowControl, Control::NoFlowControl),
+        (Control::DsrFlowControl, Control::NoFlowControl),
+    ] {
+        assert_eq!(
+            session_request(
+                &mut session,
+                &mut backend,
+                Subnegotiation::SetControl(request)
+            ),
+            Subnegotiation::SetControl(answer)
+        );
+    }
+    assert!(!backend.0.lock().unwrap().dtr);
+    assert!(backend.0.lock().unwrap().rts);
+}
+
+#[test]
+fn test_session_timed_break() {
+    let mut backend = MockBackend::default();
+    let handler = MockHandler::default();
+    let mut session = Session::with_handler(session::Config::new(), handler.clone());
+    let mut serial_out = [0; 16];
+    let mut network_out = [0; session::MAX_ANSWER_SIZE];
+
+    // The Telnet BREAK command starts a break of the configured duration
+    let data = [b'a', 255, 243, b'b'];
+    let progress = session
+        .process_network_data(&mut backend, &data, &mut serial_out, &mut network_out)
+        .unwrap();
+    assert_eq!(&serial_out[..progress.serial_produced], b"ab");
+    assert!(backend.0.lock().unwrap().break_state);
+    assert_eq!(
+        session.take_break_timer(),
+        Some(session::DEFAULT_BREAK_DURATION)
+    );
+    assert_eq!(session.take_break_timer(), None);
+    session.end_timed_break(&mut backend).unwrap();
+    assert!(!backend.0.lock().unwrap().break_state);
+    assert_eq!(
+        *handler.0.lock().unwrap(),
+        ["ToSerial a", "break true", "ToSerial b", "break false"]
+    );
+
+    // The client setting the break state itself keeps it from being ended
+    session
+        .send_break(&mut backend, Duration::from_millis(10))
+        .unwrap();
+    assert_eq!(
+        session_request(
+            &mut session,
+            &mut backend,
+            Subnegotiation::SetControl(Control::BreakOn)
+        ),
+        Subnegotiation::SetControl(Control::BreakOn)
+    );
+    session.end_timed_break(&mut backend).unwrap();
+    assert!(backend.0.lock().unwrap().break_state);
+}
+
+#[test]
+fn test_session_policy() {
+    let mut backend = MockBackend::default();
+    let mut config = session::Config::new();
+    config.policy = policy::LinePolicy {
+        baud_rate: policy::BaudRatePolicy::Nearest(&[9600, 115200]),
+        data_bits: policy::Allowed::Locked,
+        flow_control: policy::Allowed::Only(&[FlowControl::None]),
+        ..policy::LinePolicy::ANY
+    };
+    let mut session = Session::with_config(config);
+
+    // The answers carry the settings actually in effect
+    for (request, answer) in [
+        (
+            Subnegotiation::SetBaudRate(100000),
+            Subnegotiation::SetBaudRate(115200),
+        ),
+        (
+            Subnegotiation::SetBaudRate(300),
+            Subnegotiation::SetBaudRate(9600),
+        ),
+        (
+            Subnegotiation::SetDataSize(8),
+            Subnegotiation::SetDataSize(8),
+        ),
+        (
+            Subnegotiation::SetControl(Control::XonXoffFlowControl),
+            Subnegotiation::SetControl(Control::NoFlowControl),
+        ),
+    ] {
+        assert_eq!(session_request(&mut session, &mut backend, request), answer);
+    }
+    assert_eq!(backend.0.lock().unwrap().baud_rate, 9600);
+}
+
+#[test]
+fn test_session_buffers() {
+    let mut backend = MockBackend::default();
+    let mut session = Session::new();
+    let mut serial_out = [0; 4];
+    let mut network_out = [0; session::MAX_ANSWER_SIZE];
+
+    // Data is only consumed as far as the serial output has room for it
+    let progress = session
+        .process_network_data(&mut backend, b"abcdef", &mut serial_out, &mut network_out)
+        .unwrap();
+    assert_eq!(progress.consumed, 4);
+    assert_eq!(progress.serial_produced, 4);
+    assert_eq!(&serial_out, b"abcd");
+
+    // Escaped IAC bytes are forwarded unescaped to the serial port
+    let progress = session
+        .process_network_data(
+            &mut backend,
+            &[255, 255, 1],
+            &mut serial_out,
+            &mut network_out,
+        )
+        .unwrap();
+    assert_eq!(progress.consumed, 3);
+    assert_eq!(&serial_out[..progress.serial_produced], &[255, 1]);
+
+    let progress = session.process_serial_data(&[1, 255, 2], &mut network_out[..3]);
+    assert_eq!(progress.consumed, 2);
+    assert_eq!(&network_out[..progress.network_produced], &[1, 255, 255]);
+
+    let result =
+        session.process_network_data(&mut backend, b"a", &mut serial_out, &mut network_out[..1]);
+    assert!(matches!(result, Err(session::Error::BufferTooSmall)));
+}
+
+#[test]
+fn test_session_state_masks() {
+    let mut backend = MockBackend::default();
+    let mut session = Session::new();
+    // The notification sent for the current port state, if any
+    let notification = |session: &mut Session, backend: &mut MockBackend| {
+        let mut network_out = [0; session::MAX_ANSWER_SIZE];
+        let size = session
+            .process_port_state(backend, &mut network_out)
+            .unwrap();
+        let mut parser = Parser::new();
+        network_out[..size]
+            .iter()
+            .find_map(|&byte| match parser.process_byte(byte).unwrap() {
+                Some(Event::Subnegotiation(notification)) => Some(notification),
+                _ => None,
+            })
+    };
+
+    // By default the line state is not reported, the modem state is
+    assert_eq!(notification(&mut session, &mut backend), None);
+    backend.0.lock().unwrap().input.push(1);
+    assert_eq!(notification(&mut session, &mut backend), None);
+    backend.0.lock().unwrap().carrier_detect = true;
+    assert_eq!(
+        notification(&mut session, &mut backend),
+        Some(Subnegotiation::NotifyModemState(
+            ModemState::CD | ModemState::DELTA_CD
+        ))
+    );
+
+    // Lines left out of the modem state mask are neither reported nor notified about
+    let mask = ModemState::CTS | ModemState::DELTA_CTS;
+    assert_eq!(
+        session_request(
+            &mut session,
+            &mut backend,
+            Subnegotiation::SetModemStateMask(mask)
+        ),
+        Subnegotiation::SetModemStateMask(mask)
+    );
+    assert_eq!(
+        notification(&mut session, &mut backend),
+        Some(Subnegotiation::NotifyModemState(ModemState::empty()))
+    );
+    backend.0.lock().unwrap().carrier_detect = false;
+    assert_eq!(notification(&mut session, &mut backend), None);
+
+    // Only the line state changes in the mask are notified about
+    let mask = LineState::DATA_READY;
+    assert_eq!(
+        session_request(
+            &mut session,
+            &mut backend,
+            Subnegotiation::SetLinestateMask(mask)
+        ),
+        Subnegotiation::SetLinestateMask(mask)
+    );
+    backend.0.lock().unwrap().backlog = 10;
+    assert_eq!(notification(&mut session, &mut backend), None);
+    backend.0.lock().unwrap().input.clear();
+    assert_eq!(
+        notification(&mut session, &mut backend),
+        Some(Subnegotiation::NotifyLineState(LineState::empty()))
+    );
+}
+
+#[test]
+fn test_session_flow_control() {
+    let mut backend = MockBackend::default();
+    let mut session = Session::new();
+    let mut serial_out = [0; 16];
+    let mut network_out = [0; session::MAX_ANSWER_SIZE];
+    let mut packet = [0; subnegotiation::MAX_SIZE];
+
+    // The client suspending the serial data is not echoed back
+    let size = Subnegotiation::FlowControlSuspend.serialize_client(&mut packet);
+    let progress = session
+        .process_network_data(
+            &mut backend,
+            &packet[..size],
+            &mut serial_out,
+            &mut network_out,
+        )
+        .unwrap();
+    assert_eq!(progress.network_produced, 0);
+    assert!(session.is_output_suspended());
+
+    let size = Subnegotiation::FlowControlResume.serialize_client(&mut packet);
+    session
+        .process_network_data(
+            &mut backend,
+            &packet[..size],
+            &mut serial_out,
+            &mut network_out,
+        )
+        .unwrap();
+    assert!(!session.is_output_suspended());
+
+    // The client is asked to suspend once while the serial output is backlogged
+    session.set_backlog_thresholds(100, 10);
+    backend.0.lock().unwrap().backlog = 101;
+    let size = session
+        .process_port_state(&mut backend, &mut network_out)
+        .unwrap();
+    let expected = Subnegotiation::FlowControlSuspend.serialize_server(&mut packet);
+    assert_eq!(&network_out[..size], &packet[..expected]);
+    assert!(session.is_input_suspended());
+    assert_eq!(
+        session
+            .process_port_state(&mut backend, &mut network_out)
+            .unwrap(),
+        0
+    );
+
+    backend.0.lock().unwrap().backlog = 50;
+    assert_eq!(
+        session
+            .process_port_state(&mut backend, &mut network_out)
+            .unwrap(),
+        0
+    );
+
+    backend.0.lock().unwrap().backlog = 10;
+    let size = session
+        .process_port_state(&mut backend, &mut network_out)
+        .unwrap();
+    let expected = Subnegotiation::FlowControlResume.serialize_server(&mut packet);
+    assert_eq!(&network_out[..size], &packet[..expected]);
+    assert!(!session.is_input_suspended());
+}
+
+// A network connection delivering the queued packets and recording everything written to it
+#[cfg(feature = "embedded-io")]
+#[derive(Default)]
+struct MockNetwork {
+    packets: std::collections::VecDeque<Vec<u8>>,
+    written: Vec<u8>,
+    // The client stays connected once the packets run out, async reads then wait
+    open: bool,
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ErrorType for MockNetwork {
+    type Error = embedded_io::ErrorKind;
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Read for MockNetwork {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let packet = self.packets.pop_front().unwrap_or_default();
+        buf[..packet.len()].copy_from_slice(&packet);
+        Ok(packet.len())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ReadReady for MockNetwork {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Write for MockNetwork {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+fn dtr_on_packet() -> Vec<u8> {
+    let mut buf = [0; subnegotiation::MAX_SIZE];
+    let size = Subnegotiation::SetControl(Control::DtrOn).serialize_client(&mut buf);
+    let mut packet = buf[..size].to_vec();
+    packet.extend_from_slice(b"hi");
+    packet
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn test_embedded_run_once() {
+    use rfc2217_rs::embedded;
+
+    let mut backend = MockBackend::default();
+    backend.0.lock().unwrap().input.extend_from_slice(&[1, 255]);
+    let mut network = MockNetwork::default();
+    network.packets.push_back(dtr_on_packet());
+    let mut session = Session::new();
+
+    let started = embedded::run_once(&mut session, &mut network, &mut backend).unwrap();
+    assert_eq!(started, None);
+    let mut answer = [0; subnegotiation::MAX_SIZE];
+    let size = Subnegotiation::SetControl(Control::DtrOn).serialize_server(&mut answer);
+    let mut expected = answer[..size].to_vec();
+    expected.extend_from_slice(&[1, 255, 255]);
+    assert_eq!(network.written, expected);
+    assert_eq!(backend.0.lock().unwrap().output, b"hi");
+    assert!(backend.0.lock().unwrap().dtr);
+
+    // A Telnet BREAK lasts until the caller ends it, the driver says for how long
+    network.packets.push_back(vec![255, 243]);
+    let started = embedded::run_once(&mut session, &mut network, &mut backend).unwrap();
+    assert_eq!(started, Some(session::DEFAULT_BREAK_DURATION));
+    assert!(backend.0.lock().unwrap().break_state);
+    assert_eq!(session.take_break_timer(), None);
+    session.end_timed_break(&mut backend).unwrap();
+    assert!(!backend.0.lock().unwrap().break_state);
+
+    let result = embedded::run_once(&mut session, &mut network, &mut backend);
+    assert!(matches!(result, Err(embedded::Error::Disconnected)));
+
+    // A port that stops taking data fails instead of being retried forever
+    backend.0.lock().unwrap().stuck = true;
+    network.packets.push_back(b"hi".to_vec());
+    let result = embedded::run_once(&mut session, &mut network, &mut backend);
+    assert!(matches!(result, Err(embedded::Error::WriteZero)));
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Read for MockNetwork {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.open && self.packets.is_empty() {
+            std::future::pending::<()>().await;
+        }
+        embedded_io::Read::read(self, buf)
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Write for MockNetwork {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        embedded_io::Write::write(self, buf)
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io::ErrorType for MockBackend {
+    type Error = io::Error;
+}
+
+// Serial data is never received, the pending read is dropped when the network receives data
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Read for MockBackend {
+    async fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        std::future::pending().await
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Write for MockBackend {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        SerialBackend::write(self, buf)
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+#[test]
+fn test_embedded_flow_control_suspend() {
+    use rfc2217_rs::embedded;
+    use std::future::Future;
+    use std::task::{Context, Waker};
+
+    let sent = |written: &[u8], notification: Subnegotiation| {
+        let mut packet = [0; subnegotiation::MAX_SIZE];
+        let size = notification.serialize_server(&mut packet);
+        written
+            .windows(size)
+            .any(|window| window == &packet[..size])
+    };
+
+    // The data written to the port stays backlogged, so the client is asked to suspend
+    let mut backend = MockBackend::default();
+    let mut network = MockNetwork::default();
+    network.packets.push_back(b"hello".to_vec());
+    let mut session = Session::new();
+    session.set_backlog_thresholds(4, 0);
+    embedded::run_once(&mut session, &mut network, &mut backend).unwrap();
+    assert!(sent(&network.written, Subnegotiation::FlowControlSuspend));
+
+    // The async driver checks on the backlog every tick until the client can resume
+    let mut backend = MockBackend::default();
+    let state = backend.clone();
+    let mut network = MockNetwork {
+        open: true,
+        ..MockNetwork::default()
+    };
+    network.packets.push_back(b"hello".to_vec());
+    let mut session = Session::new();
+    session.set_backlog_thresholds(4, 0);
+    let mut delay = MockDelay::default();
+    {
+        let run = embedded::run(&mut session, &mut network, &mut backend, &mut delay);
+        let mut run = std::pin::pin!(run);
+        let mut context = Context::from_waker(Waker::noop());
+        assert!(run.as_mut().poll(&mut context).is_pending());
+        state.0.lock().unwrap().backlog = 0;
+        for _ in 0..3 {
+            assert!(run.as_mut().poll(&mut context).is_pending());
+        }
+    }
+    assert!(sent(&network.written, Subnegotiation::FlowControlSuspend));
+    assert!(sent(&network.written, Subnegotiation::FlowControlResume));
+    assert!(delay.0 > 0);
+}
+
+// A delay that passes the next time it is polled, adding up the time waited in nanoseconds
+#[cfg(feature = "embedded-io-async")]
+#[derive(Default)]
+struct MockDelay(u64);
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_hal_async::delay::DelayNs for MockDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.0 += u64::from(ns);
+        let mut started = false;
+        std::future::poll_fn(|_| match std::mem::replace(&mut started, true) {
+            true => std::task::Poll::Ready(()),
+            false => std::task::Poll::Pending,
+        })
+        .await
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+#[test]
+fn test_embedded_async_run() {
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    let mut backend = MockBackend::default();
+    let mut network = MockNetwork::default();
+    network.packets.push_back(dtr_on_packet());
+    let mut session = Session::new();
+    let mut delay = MockDelay::default();
+
+    // The mock transports never wait, so the driver completes without being woken up
+    let run = rfc2217_rs::embedded::run(&mut session, &mut network, &mut backend, &mut delay);
+    let mut context = Context::from_waker(Waker::noop());
+    let Poll::Ready(result) = std::pin::pin!(run).poll(&mut context) else {
+        panic!("The driver did not finish");
+    };
+    result.unwrap();
+
+    let state = backend.0.lock().unwrap();
+    assert_eq!(state.output, b"hi");
+    assert!(state.dtr);
+    // The default settings are restored once the client disconnects
+    assert_eq!(state.baud_rate, 9600);
+}
+
+#[cfg(feature = "embedded-io-async")]
+#[test]
+fn test_embedded_async_break() {
+    use std::future::Future;
+    use std::task::{Context, Waker};
+
+    let mut backend = MockBackend::default();
+    let mut network = MockNetwork {
+        open: true,
+        ..MockNetwork::default()
+    };
+    network.packets.push_back(vec![255, 243]);
+    let handler = MockHandler::default();
+    let mut session = Session::with_handler(session::Config::new(), handler.clone());
+    let mut delay = MockDelay::default();
+
+    // The break is timed while the client stays connected, every poll passes a tick
+    {
+        let run = rfc2217_rs::embedded::run(&mut session, &mut network, &mut backend, &mut delay);
+        let mut run = std::pin::pin!(run);
+        let mut context = Context::from_waker(Waker::noop());
+        for _ in 0..30 {
+            assert!(run.as_mut().poll(&mut context).is_pending());
+        }
+    }
+
+    assert!(!backend.0.lock().unwrap().break_state);
+    assert_eq!(
+        *handler.0.lock().unwrap(),
+        ["connect", "break true", "break false"]
+    );
+    assert_eq!(
+        u128::from(delay.0),
+        session::DEFAULT_BREAK_DURATION.as_nanos()
+    );
+}
+
+// The async server opens the port by name, a pseudo terminal stands in for the device.
+// Like in the servers it is meant for, the runtime runs the tasks on several threads.
+#[cfg(all(feature = "tokio", unix))]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_server() {
+    use serialport::{SerialPort, TTYPort};
+
+    let (mut device, port) = TTYPort::pair().unwrap();
+    let name = port.name().unwrap();
+    drop(port);
+    let server = AsyncServer::new(&name, "127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run().await });
+
+    // The clients block, so they run beside the server
+    tokio::task::spawn_blocking(move || {
+        // Connecting negotiates COM-PORT-OPTION and the line settings
+        let mut client = Client::new(addr, 115200).unwrap();
+        client.set_timeout(Duration::from_secs(3)).unwrap();
+        device.set_timeout(Duration::from_secs(3)).unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut data = [0; 4];
+        device.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"ping");
+        device.write_all(b"pong").unwrap();
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"pong");
+
+        // Only one client is served at a time
+        assert!(Client::new(addr, 115200).is_err());
+    })
+    .await
+    .unwrap();
+}
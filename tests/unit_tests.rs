@@ -119,7 +119,8 @@ fn test_baud_subnegotiation_generated() {
 
 #[test]
 fn test_baud_subnegotiation_containing_iac() {
-    let mut subneg: [u8; 10] = [0; 10];
+    // Both IAC bytes of the baud rate are escaped
+    let mut subneg: [u8; 12] = [0; 12];
     let expected_baudrate = 0x0000FFFF;
     Subnegotiation::SetBaudRate(expected_baudrate).serialize_client(&mut subneg);
 
@@ -145,7 +146,7 @@ fn test_baud_subnegotiation_containing_iac() {
 #[test]
 fn test_parity_subnegotiation() {
     let mut subneg = [0; 7];
-    Subnegotiation::SetParity(1).serialize_client(&mut subneg);
+    Subnegotiation::SetParity(com_port::Parity::None).serialize_client(&mut subneg);
 
     let mut parser = Parser::new();
 
@@ -156,7 +157,9 @@ fn test_parity_subnegotiation() {
 
     assert_eq!(
         result,
-        Ok(Some(Event::Subnegotiation(Subnegotiation::SetParity(1))))
+        Ok(Some(Event::Subnegotiation(Subnegotiation::SetParity(
+            com_port::Parity::None
+        ))))
     );
 }
 
@@ -179,3 +182,416 @@ fn test_flow_control_suspend_subnegotiation() {
         )))
     );
 }
+
+#[test]
+fn test_subnegotiation_try_deserialize_errors() {
+    assert_eq!(
+        Subnegotiation::try_deserialize(&[255, 250, 44, 1, 255]),
+        Err(subnegotiation::Error::TooShort(5))
+    );
+    assert_eq!(
+        Subnegotiation::try_deserialize(&[255, 250, 44, 1, 0, 37, 128, 0, 0, 255]),
+        Err(subnegotiation::Error::MissingIacSe)
+    );
+    assert_eq!(
+        Subnegotiation::try_deserialize(&[255, 250, 44, 1, 0, 37, 255, 240]),
+        Err(subnegotiation::Error::WrongDataSize {
+            option_code: 1,
+            size: 2
+        })
+    );
+    assert_eq!(
+        Subnegotiation::try_deserialize(&[255, 250, 44, 42, 1, 255, 240]),
+        Err(subnegotiation::Error::UnknownOption(42))
+    );
+    assert_eq!(
+        Negotiation::try_deserialize(&[255, 240, 1]),
+        Err(negotiation::Error::UnknownIntent(240))
+    );
+}
+
+#[test]
+fn test_malformed_subnegotiation_recovery() {
+    let mut parser = Parser::new();
+
+    let mut result: Result<Option<Event>, Error> = Ok(None);
+    for byte in [255, 250, 44, 42, 1, 255, 240] {
+        result = parser.process_byte(byte);
+    }
+    assert_eq!(
+        result,
+        Err(Error::Subnegotiation(subnegotiation::Error::UnknownOption(
+            42
+        )))
+    );
+
+    assert_eq!(parser.process_byte(7), Ok(Some(Event::Data(7))));
+}
+
+#[test]
+fn test_com_port_values_round_trip() {
+    for byte in 0..=u8::MAX {
+        assert_eq!(com_port::Control::from_u8(byte).to_u8(), byte);
+        assert_eq!(com_port::Parity::from_u8(byte).to_u8(), byte);
+        assert_eq!(com_port::StopBits::from_u8(byte).to_u8(), byte);
+        assert_eq!(com_port::Purge::from_u8(byte).to_u8(), byte);
+    }
+    assert_eq!(com_port::Control::from_u8(11), com_port::Control::RtsOn);
+    assert_eq!(com_port::Parity::from_u8(4), com_port::Parity::Mark);
+    assert_eq!(
+        com_port::StopBits::from_u8(3),
+        com_port::StopBits::OnePointFive
+    );
+    assert!(com_port::ModemState::from_u8(0x30).contains(com_port::ModemState::CTS));
+}
+
+#[test]
+fn test_option_table_answers() {
+    use negotiation::{Change, Intent, Option, OptionTable, Side};
+
+    let mut options = OptionTable::new();
+    options.support(Side::Remote, Option::Binary);
+
+    let reply = options.receive(Negotiation {
+        intent: Intent::Will,
+        option: Option::Binary,
+    });
+    assert_eq!(
+        reply.answer,
+        Some(Negotiation {
+            intent: Intent::Do,
+            option: Option::Binary,
+        })
+    );
+    assert_eq!(
+        reply.change,
+        Some(Change::Enabled(Side::Remote, Option::Binary))
+    );
+
+    // Repeated requests for an enabled option are not answered, avoiding loops
+    let reply = options.receive(Negotiation {
+        intent: Intent::Will,
+        option: Option::Binary,
+    });
+    assert_eq!(reply.answer, None);
+
+    let reply = options.receive(Negotiation {
+        intent: Intent::Wont,
+        option: Option::Binary,
+    });
+    assert_eq!(
+        reply.answer,
+        Some(Negotiation {
+            intent: Intent::Dont,
+            option: Option::Binary,
+        })
+    );
+    assert_eq!(
+        reply.change,
+        Some(Change::Disabled(Side::Remote, Option::Binary))
+    );
+
+    // Unsupported options are refused
+    let reply = options.receive(Negotiation {
+        intent: Intent::Do,
+        option: Option::Echo,
+    });
+    assert_eq!(
+        reply.answer,
+        Some(Negotiation {
+            intent: Intent::Wont,
+            option: Option::Echo,
+        })
+    );
+}
+
+#[test]
+fn test_option_table_requests() {
+    use negotiation::{Intent, Option, OptionState, OptionTable, Queue, Reply, Side};
+
+    let mut options = OptionTable::new();
+    assert_eq!(
+        options.request_enable(Side::Local, Option::ComPort),
+        Some(Negotiation {
+            intent: Intent::Will,
+            option: Option::ComPort,
+        })
+    );
+    // Changing our mind while the request is pending is queued instead of sent
+    assert_eq!(
+        options.request_disable(Side::Local, Option::ComPort),
+        Reply::default()
+    );
+    assert_eq!(
+        options.state(Side::Local, Option::ComPort),
+        OptionState::WantYes(Queue::Opposite)
+    );
+
+    let reply = options.receive(Negotiation {
+        intent: Intent::Do,
+        option: Option::ComPort,
+    });
+    assert_eq!(
+        reply.answer,
+        Some(Negotiation {
+            intent: Intent::Wont,
+            option: Option::ComPort,
+        })
+    );
+    assert_eq!(reply.change, None);
+
+    let reply = options.receive(Negotiation {
+        intent: Intent::Dont,
+        option: Option::ComPort,
+    });
+    assert_eq!(reply.answer, None);
+    assert!(!options.is_enabled(Side::Local, Option::ComPort));
+}
+
+#[test]
+fn test_option_table_disable_request() {
+    use negotiation::{Change, Intent, Option, OptionTable, Reply, Side};
+
+    let mut options = OptionTable::new();
+    options.support(Side::Remote, Option::Binary);
+    options.receive(Negotiation {
+        intent: Intent::Will,
+        option: Option::Binary,
+    });
+
+    // The option stops being used as soon as we ask to disable it
+    let reply = options.request_disable(Side::Remote, Option::Binary);
+    assert_eq!(
+        reply.answer,
+        Some(Negotiation {
+            intent: Intent::Dont,
+            option: Option::Binary,
+        })
+    );
+    assert_eq!(
+        reply.change,
+        Some(Change::Disabled(Side::Remote, Option::Binary))
+    );
+    assert!(!options.is_enabled(Side::Remote, Option::Binary));
+    let reply = options.receive(Negotiation {
+        intent: Intent::Wont,
+        option: Option::Binary,
+    });
+    assert_eq!(reply, Reply::default());
+
+    // Asking to enable it again while the disable is pending reports it enabled once
+    // the other side ends up using it
+    options.receive(Negotiation {
+        intent: Intent::Will,
+        option: Option::Binary,
+    });
+    options.request_disable(Side::Remote, Option::Binary);
+    options.request_enable(Side::Remote, Option::Binary);
+    let reply = options.receive(Negotiation {
+        intent: Intent::Will,
+        option: Option::Binary,
+    });
+    assert_eq!(
+        reply.change,
+        Some(Change::Enabled(Side::Remote, Option::Binary))
+    );
+}
+
+#[test]
+fn test_encoder_escapes_iac() {
+    let mut out = [0; 4];
+    let encoded = encoder::encode_data(&[1, 255, 2, 255], &mut out);
+    assert_eq!(encoded.consumed, 3);
+    assert_eq!(&out[..encoded.produced], &[1, 255, 255, 2]);
+
+    let mut out = [0; subnegotiation::MAX_SIZE];
+    let mut data = [0; subnegotiation::MAX_DATA_SIZE];
+    data[..255].fill(255);
+    for subneg in [
+        Subnegotiation::SetBaudRate(0xFF00FFFF),
+        Subnegotiation::NotifyModemState(com_port::ModemState::all()),
+        Subnegotiation::Unsupported {
+            base_option_code: 42,
+            option_code: 1,
+            data,
+            data_cnt: 255,
+        },
+    ] {
+        let size = encoder::encode_client_subnegotiation(&subneg, &mut out).unwrap();
+        assert_eq!(size, subneg.serialized_size());
+
+        let mut parser = Parser::new();
+        let mut result = Ok(None);
+        for &byte in &out[..size] {
+            result = parser.process_byte(byte);
+        }
+        assert_eq!(result, Ok(Some(Event::Subnegotiation(subneg))));
+    }
+
+    assert_eq!(
+        encoder::encode_server_subnegotiation(&Subnegotiation::SetBaudRate(255), &mut out[..10]),
+        Err(encoder::Error::BufferTooSmall { required: 11 })
+    );
+}
+
+#[test]
+fn test_parser_feed() {
+    let mut input = b"hello".to_vec();
+    input.extend_from_slice(&[255, 255, 1, 2]);
+    input.extend_from_slice(&[255, 251, 44]);
+    input.extend_from_slice(b"world");
+
+    let mut parser = Parser::new();
+    let events: Vec<_> = parser.feed(&input).collect();
+    assert_eq!(
+        events,
+        [
+            Ok(Event::Data(&b"hello"[..])),
+            Ok(Event::Data(&[255][..])),
+            Ok(Event::Data(&[1, 2][..])),
+            Ok(Event::Negotiation(Negotiation {
+                intent: negotiation::Intent::Will,
+                option: negotiation::Option::ComPort,
+            })),
+            Ok(Event::Data(&b"world"[..])),
+        ]
+    );
+
+    // Packets split between slices are completed by the following one
+    let mut subneg = [0; subnegotiation::MAX_SIZE];
+    let size = Subnegotiation::SetBaudRate(9600).serialize_client(&mut subneg);
+    assert_eq!(parser.feed(&subneg[..3]).count(), 0);
+    assert_eq!(
+        parser.feed(&subneg[3..size]).collect::<Vec<_>>(),
+        [Ok(Event::Subnegotiation(Subnegotiation::SetBaudRate(9600)))]
+    );
+}
+
+#[test]
+fn test_baud_rate_policy() {
+    use rfc2217_rs::policy::BaudRatePolicy;
+
+    assert_eq!(BaudRatePolicy::Any.apply(12345), Some(12345));
+    assert_eq!(
+        BaudRatePolicy::Only(&[9600, 115200]).apply(9600),
+        Some(9600)
+    );
+    assert_eq!(BaudRatePolicy::Only(&[9600, 115200]).apply(19200), None);
+    let clamp = BaudRatePolicy::Clamp {
+        min: 1200,
+        max: 115200,
+    };
+    assert_eq!(clamp.apply(300), Some(1200));
+    assert_eq!(clamp.apply(921600), Some(115200));
+    assert_eq!(clamp.apply(57600), Some(57600));
+    let nearest = BaudRatePolicy::Nearest(&[9600, 57600, 115200]);
+    assert_eq!(nearest.apply(100000), Some(115200));
+    assert_eq!(nearest.apply(1), Some(9600));
+    assert_eq!(BaudRatePolicy::Nearest(&[]).apply(9600), None);
+    assert_eq!(BaudRatePolicy::Locked.apply(9600), None);
+}
+
+#[test]
+fn test_ip_network() {
+    use rfc2217_rs::access::IpNetwork;
+
+    let network: IpNetwork = "10.1.0.0/16".parse().unwrap();
+    assert!(network.contains("10.1.200.3".parse().unwrap()));
+    assert!(!network.contains("10.2.0.1".parse().unwrap()));
+    assert!(network.contains("::ffff:10.1.0.9".parse().unwrap()));
+    assert!(!network.contains("::1".parse().unwrap()));
+
+    let host: IpNetwork = "fe80::1".parse().unwrap();
+    assert!(host.contains("fe80::1".parse().unwrap()));
+    assert!(!host.contains("fe80::2".parse().unwrap()));
+    let everyone: IpNetwork = "0.0.0.0/0".parse().unwrap();
+    assert!(everyone.contains("192.0.2.1".parse().unwrap()));
+
+    assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+    assert!("10.0.0/8".parse::<IpNetwork>().is_err());
+}
+
+#[test]
+fn test_device_selector() {
+    use rfc2217_rs::selector::{DeviceSelector, Error};
+    use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+    let usb: DeviceSelector = "usb:303a:1001".parse().unwrap();
+    assert_eq!(
+        usb,
+        DeviceSelector::Usb {
+            vid: 0x303a,
+            pid: 0x1001
+        }
+    );
+    let serial_number: DeviceSelector = "serial-number:ABC123".parse().unwrap();
+    assert_eq!(
+        "/dev/ttyUSB0".parse::<DeviceSelector>().unwrap(),
+        DeviceSelector::Path("/dev/ttyUSB0".to_owned())
+    );
+    assert!("usb:303a".parse::<DeviceSelector>().is_err());
+    assert!("usb:303a:10001".parse::<DeviceSelector>().is_err());
+
+    let port = SerialPortInfo {
+        port_name: "/dev/ttyACM0".to_owned(),
+        port_type: SerialPortType::UsbPort(UsbPortInfo {
+            vid: 0x303a,
+            pid: 0x1001,
+            serial_number: Some("ABC123".to_owned()),
+            manufacturer: None,
+            product: None,
+        }),
+    };
+    assert!(usb.matches(&port));
+    assert!(serial_number.matches(&port));
+    assert!(!DeviceSelector::SerialNumber("ABC".to_owned()).matches(&port));
+    assert!(!DeviceSelector::Usb {
+        vid: 0x303a,
+        pid: 2
+    }
+    .matches(&port));
+
+    // Paths are opened without looking for them
+    assert_eq!(
+        DeviceSelector::Path("/dev/missing".to_owned())
+            .resolve()
+            .unwrap(),
+        "/dev/missing"
+    );
+    assert!(matches!(
+        "by-id:no-such-device-*"
+            .parse::<DeviceSelector>()
+            .unwrap()
+            .resolve(),
+        Err(Error::NotFound)
+    ));
+}
+
+#[test]
+fn test_subnegotiation_display() {
+    use com_port::{Control, ModemState};
+
+    assert_eq!(Subnegotiation::SetBaudRate(9600).name(), "SET-BAUDRATE");
+    assert_eq!(
+        Subnegotiation::SetBaudRate(9600).to_string(),
+        "SET-BAUDRATE 9600"
+    );
+    assert_eq!(
+        Subnegotiation::SetControl(Control::DtrOff).to_string(),
+        "SET-CONTROL DtrOff"
+    );
+    assert_eq!(
+        Subnegotiation::NotifyModemState(ModemState::CD | ModemState::DELTA_CD).to_string(),
+        "NOTIFY-MODEMSTATE 0x88"
+    );
+    assert_eq!(
+        Subnegotiation::FlowControlSuspend.to_string(),
+        "FLOWCONTROL-SUSPEND"
+    );
+    let mut data = [0; subnegotiation::MAX_DATA_SIZE];
+    data[..4].copy_from_slice(b"lab\n");
+    assert_eq!(
+        Subnegotiation::SetSignature { data, size: 4 }.to_string(),
+        "SIGNATURE \"lab\\n\""
+    );
+}